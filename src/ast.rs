@@ -1,8 +1,10 @@
 pub mod parser;
 
+use std::fmt;
+
 use crate::lexer::tokens::{BinaryOperatorKind, LiteralKind};
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Ast {
     EOF,
     ValueNode(LiteralKind),
@@ -11,30 +13,349 @@ pub enum Ast {
     BinaryNode {
         left: Box<Ast>,
         right: Box<Ast>,
-        op: BinaryOperatorKind
+        op: BinaryOperatorKind,
+        start: usize
+    },
+    /// `assignee op= value`, the assignee is a variable, `*reference` or `vector[index]`
+    CompoundAssignNode {
+        assignee: Box<Ast>,
+        value: Box<Ast>,
+        op: BinaryOperatorKind,
+        start: usize
     },
     LetNode {
         assignee: String,
-        value: Box<Ast>
+        value: Box<Ast>,
+        start: usize
+    },
+    /// `let (names) = value`, takes the tuple apart. `_` leaves an element out
+    DestructureNode {
+        names: Vec<String>,
+        value: Box<Ast>,
+        start: usize
+    },
+    /// `&name`, or `&mut name` when `mutable`. Only passed to parameters taking references
+    ReferenceNode {
+        name: String,
+        mutable: bool,
+        start: usize
+    },
+    /// `*name`, the value the reference `name` points to
+    DerefNode {
+        name: String,
+        start: usize
     },
+    /// `(elements)`, which functions return several values in
+    TupleNode {
+        elements: Vec<Ast>,
+        start: usize
+    },
+    /// An `if` is also an expression when both of its blocks end with one
     IfNode {
         condition: Box<Ast>,
-        body: Vec<Ast>
+        body: Vec<Ast>,
+        else_body: Option<Vec<Ast>>,
+        start: usize
+    },
+    /// `for variable in from..to step step`, the range includes `to` when `inclusive`
+    ForNode {
+        variable: String,
+        from: Box<Ast>,
+        to: Box<Ast>,
+        inclusive: bool,
+        step: Option<Box<Ast>>,
+        body: Vec<Ast>,
+        start: usize
+    },
+    /// `for variable in vector`
+    ForEachNode {
+        variable: String,
+        vector: Box<Ast>,
+        body: Vec<Ast>,
+        start: usize
     },
     WhileNode {
         condition: Box<Ast>,
-        body: Vec<Ast>
+        body: Vec<Ast>,
+        start: usize
+    },
+    /// `|params| body`, captures the values of the variables it uses
+    ClosureNode {
+        params: Vec<String>,
+        body: Box<Ast>,
+        start: usize
     },
     CallNode(Proto),
+    /// `[elements]`, a new vector
+    VectorNode {
+        elements: Vec<Ast>,
+        start: usize
+    },
+    /// `vector[index]`, starts at the `[`
+    IndexNode {
+        vector: Box<Ast>,
+        index: Box<Ast>,
+        start: usize
+    },
     ExternNode(Proto),
+    ImportNode {
+        path: String,
+        start: usize
+    },
+    /// `const name = value` or `static name = value` when `mutable`
+    GlobalNode {
+        name: String,
+        value: Box<Ast>,
+        mutable: bool,
+        public: bool,
+        start: usize
+    },
+    /// `enum name { variants }`
+    EnumNode {
+        name: String,
+        variants: Vec<Variant>,
+        public: bool,
+        start: usize
+    },
+    /// `trait name { prototypes }`, methods values of the enums implementing it have
+    TraitNode {
+        name: String,
+        methods: Vec<Proto>,
+        public: bool,
+        start: usize
+    },
+    /// `impl name for target { functions }`, the functions are named after the methods they implement
+    ImplNode {
+        name: String,
+        target: String,
+        functions: Vec<Ast>,
+        start: usize
+    },
+    /// `receiver.method(args)`, starts at the `.`
+    MethodNode {
+        receiver: Box<Ast>,
+        method: String,
+        args: Vec<Ast>,
+        start: usize
+    },
+    /// `match value { arms }`, an expression when every arm ends with one
+    MatchNode {
+        value: Box<Ast>,
+        arms: Vec<Arm>,
+        start: usize
+    },
     FunctionNode {
+        proto: Proto,
+        body: Vec<Ast>,
+        public: bool
+    },
+    TestNode {
         proto: Proto,
         body: Vec<Ast>
     },
-    RetNode(Box<Ast>)
+    RetNode {
+        value: Box<Ast>,
+        start: usize
+    },
+    /// `defer value`, evaluated whenever the block it is in is left, the last deferred first
+    DeferNode {
+        value: Box<Ast>,
+        start: usize
+    }
 }
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Proto {
     pub(crate) name: String,
-    pub(crate) args: Vec<Ast>
+    pub(crate) args: Vec<Ast>,
+    /// `<T, U>` after the name of a definition
+    pub(crate) generics: Vec<String>,
+    /// The types written after the parameters of a definition, and after its `->`
+    pub(crate) types: Vec<Option<Type>>,
+    pub(crate) returns: Option<Type>,
+    pub(crate) start: usize
+}
+impl Proto {
+    /// How many values the function returns
+    pub fn results(&self) -> usize {
+        match &self.returns {
+            Some(Type::Tuple(types)) => types.len(),
+            _ => 1,
+        }
+    }
+    /// Whether every parameter takes a mutable reference, a reference, or a value when `None`
+    pub fn references(&self) -> Vec<Option<bool>> {
+        (0..self.args.len())
+            .map(|i| match self.types.get(i) {
+                Some(Some(Type::Reference { mutable, .. })) => Some(*mutable),
+                _ => None,
+            })
+            .collect()
+    }
+    /// How many arguments every parameter typed `fn(..)` passes to the function it takes, `None` for the others
+    pub fn arities(&self) -> Vec<Option<usize>> {
+        (0..self.args.len())
+            .map(|i| match self.types.get(i) {
+                Some(Some(Type::Function(params))) => Some(params.len()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+/// A type written after a parameter or `->`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Type {
+    /// `f64`, an enum or a type parameter
+    Name(String),
+    /// `(types)`, only returned by functions
+    Tuple(Vec<Type>),
+    /// `&ty`, or `&mut ty` when `mutable`, only taken by parameters
+    Reference {
+        mutable: bool,
+        ty: Box<Type>
+    },
+    /// `fn(types)`, a function or a closure taking numbers and returning one, only taken by parameters
+    Function(Vec<Type>)
+}
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Name(name) => f.write_str(name),
+            Type::Tuple(types) => {
+                let types: Vec<String> = types.iter().map(ToString::to_string).collect();
+                write!(f, "({})", types.join(", "))
+            }
+            Type::Reference { mutable: true, ty } => write!(f, "&mut {ty}"),
+            Type::Reference { ty, .. } => write!(f, "&{ty}"),
+            Type::Function(params) => {
+                let params: Vec<String> = params.iter().map(ToString::to_string).collect();
+                write!(f, "fn({})", params.join(", "))
+            }
+        }
+    }
+}
+/// `name(fields)`, or just `name` without fields
+#[derive(Clone, Debug, PartialEq)]
+pub struct Variant {
+    pub(crate) name: String,
+    pub(crate) fields: Vec<String>,
+    pub(crate) start: usize
+}
+/// `pattern => body`, the body is either a block or a single expression
+#[derive(Clone, Debug, PartialEq)]
+pub struct Arm {
+    pub(crate) pattern: Pattern,
+    pub(crate) body: Vec<Ast>,
+    /// Whether the body is written without curly braces
+    pub(crate) expression: bool,
+    pub(crate) start: usize
+}
+#[derive(Clone, Debug, PartialEq)]
+pub enum Pattern {
+    /// `enum::variant(bindings)`, `_` binds nothing
+    Variant {
+        name: String,
+        bindings: Vec<String>
+    },
+    /// A number, which may have a `-` before it
+    Number(f64),
+    /// `from..to`, or `from..=to` when `inclusive`
+    Range {
+        from: f64,
+        to: f64,
+        inclusive: bool
+    },
+    /// `_`
+    Wildcard
+}
+impl Ast {
+    /// Blocks of statements nested right inside the node
+    pub fn blocks(&self) -> Vec<&[Ast]> {
+        match self {
+            Ast::IfNode { body, else_body, .. } => std::iter::once(body.as_slice()).chain(else_body.as_deref()).collect(),
+            Ast::WhileNode { body, .. }
+            | Ast::ForNode { body, .. }
+            | Ast::ForEachNode { body, .. }
+            | Ast::FunctionNode { body, .. }
+            | Ast::TestNode { body, .. } => vec![body],
+            Ast::MatchNode { arms, .. } => arms.iter().map(|arm| arm.body.as_slice()).collect(),
+            _ => vec![],
+        }
+    }
+    /// Expressions nested right inside the node, apart from the ones in its blocks
+    pub fn expressions(&self) -> Vec<&Ast> {
+        match self {
+            Ast::UnitNode(value)
+            | Ast::LetNode { value, .. }
+            | Ast::DestructureNode { value, .. }
+            | Ast::RetNode { value, .. }
+            | Ast::DeferNode { value, .. }
+            | Ast::GlobalNode { value, .. }
+            | Ast::MatchNode { value, .. }
+            | Ast::IfNode { condition: value, .. }
+            | Ast::WhileNode { condition: value, .. }
+            | Ast::ForEachNode { vector: value, .. }
+            | Ast::ClosureNode { body: value, .. } => vec![value],
+            Ast::BinaryNode { left, right, .. } | Ast::CompoundAssignNode { assignee: left, value: right, .. } => vec![left, right],
+            Ast::IndexNode { vector, index, .. } => vec![vector, index],
+            Ast::ForNode { from, to, step, .. } => [from, to].into_iter().chain(step).map(AsRef::as_ref).collect(),
+            Ast::CallNode(proto) => proto.args.iter().collect(),
+            Ast::VectorNode { elements, .. } | Ast::TupleNode { elements, .. } => elements.iter().collect(),
+            Ast::MethodNode { receiver, args, .. } => std::iter::once(receiver.as_ref()).chain(args).collect(),
+            _ => vec![],
+        }
+    }
+    /// Whether the node evaluates to a number when it ends a block
+    pub fn produces_value(&self) -> bool {
+        match self {
+            Ast::ValueNode(_)
+            | Ast::IdentifierNode(_)
+            | Ast::UnitNode(_)
+            | Ast::BinaryNode { .. }
+            | Ast::CallNode(_)
+            | Ast::ClosureNode { .. }
+            | Ast::VectorNode { .. }
+            | Ast::IndexNode { .. }
+            | Ast::MethodNode { .. }
+            | Ast::DerefNode { .. }
+            | Ast::CompoundAssignNode { .. } => true,
+            Ast::IfNode { body, else_body: Some(else_body), .. } => {
+                [body, else_body].iter().all(|block| block.last().is_some_and(Ast::produces_value))
+            }
+            Ast::MatchNode { arms, .. } => arms.iter().all(|arm| arm.body.last().is_some_and(Ast::produces_value)),
+            _ => false,
+        }
+    }
+    /// Position of the token the node starts with, if the node remembers it
+    pub fn start(&self) -> Option<usize> {
+        match self {
+            Ast::BinaryNode { start, .. }
+            | Ast::LetNode { start, .. }
+            | Ast::DestructureNode { start, .. }
+            | Ast::TupleNode { start, .. }
+            | Ast::ReferenceNode { start, .. }
+            | Ast::DerefNode { start, .. }
+            | Ast::CompoundAssignNode { start, .. }
+            | Ast::IfNode { start, .. }
+            | Ast::WhileNode { start, .. }
+            | Ast::ForNode { start, .. }
+            | Ast::ForEachNode { start, .. }
+            | Ast::ClosureNode { start, .. }
+            | Ast::VectorNode { start, .. }
+            | Ast::IndexNode { start, .. }
+            | Ast::GlobalNode { start, .. }
+            | Ast::EnumNode { start, .. }
+            | Ast::TraitNode { start, .. }
+            | Ast::ImplNode { start, .. }
+            | Ast::MethodNode { start, .. }
+            | Ast::MatchNode { start, .. }
+            | Ast::RetNode { start, .. }
+            | Ast::DeferNode { start, .. }
+            | Ast::ImportNode { start, .. } => Some(*start),
+            Ast::CallNode(proto) | Ast::ExternNode(proto) | Ast::FunctionNode { proto, .. } | Ast::TestNode { proto, .. } => {
+                Some(proto.start)
+            }
+            Ast::UnitNode(unit) => unit.start(),
+            _ => None,
+        }
+    }
 }
@@ -1,47 +1,105 @@
-use super::Proto;
+use super::{Arm, Pattern, Proto, Type, Variant};
 
 use crate::ast::Ast;
 use crate::abort_with_message;
 use crate::input::CursoredFile;
 use crate::lexer::next_token;
-use crate::lexer::tokens::{BinaryOperatorKind, KeywordKind, OperatorKind, TokenKind};
+use crate::lexer::tokens::{BinaryOperatorKind, ComparisionOpKind, KeywordKind, LiteralKind, OperatorKind, Token, TokenKind};
 
 pub fn parse(input: &mut CursoredFile) -> Vec<Ast> {
     let mut ast = Vec::new();
     loop {
         let token = next_token(input);
         match &token.kind {
-            TokenKind::Keyword(KeywordKind::Fn) => ast.push(Ast::FunctionNode {
-                proto: parse_prototype(input, true),
-                body: parse_block(input),
-            }),
+            TokenKind::Keyword(KeywordKind::Fn | KeywordKind::Const | KeywordKind::Static | KeywordKind::Enum | KeywordKind::Trait) => {
+                ast.push(parse_item(input, &token, false));
+            }
+            TokenKind::Keyword(KeywordKind::Pub) => {
+                let item = next_token(input);
+                if !matches!(
+                    item.kind,
+                    TokenKind::Keyword(KeywordKind::Fn | KeywordKind::Const | KeywordKind::Static | KeywordKind::Enum | KeywordKind::Trait)
+                ) {
+                    abort_with_message!(item, input, "expected `fn`, `const`, `static`, `enum` or `trait`");
+                }
+                ast.push(parse_item(input, &item, true));
+            }
+            TokenKind::Keyword(KeywordKind::Impl) => ast.push(parse_impl(input, token.start)),
             TokenKind::Keyword(KeywordKind::Extern) => {
-                ast.push(Ast::ExternNode(parse_prototype(input, true)));
+                let proto = parse_prototype(input, true);
+                if !proto.generics.is_empty() {
+                    abort_with_message!(token, input, "extern functions cannot be generic");
+                }
+                if proto.results() > 1 {
+                    abort_with_message!(token, input, "extern functions cannot return tuples");
+                }
+                if proto.references().iter().any(Option::is_some) {
+                    abort_with_message!(token, input, "extern functions cannot take references");
+                }
+                ast.push(Ast::ExternNode(proto));
+            }
+            TokenKind::Keyword(KeywordKind::Test) => {
+                let fn_token = next_token(input);
+                if fn_token.kind != TokenKind::Keyword(KeywordKind::Fn) {
+                    abort_with_message!(fn_token, input, "expected `fn`");
+                }
+                let proto = parse_prototype(input, true);
+                if !proto.args.is_empty() || !proto.generics.is_empty() {
+                    abort_with_message!(fn_token, input, "test functions accept no parameters");
+                }
+                ast.push(Ast::TestNode {
+                    proto,
+                    body: parse_block(input),
+                });
+            }
+            TokenKind::Keyword(KeywordKind::Import) => {
+                let path = next_token(input);
+                let TokenKind::Literal(LiteralKind::Str(ref quoted)) = path.kind else {
+                    abort_with_message!(path, input, "expected a path in quotes")
+                };
+                ast.push(Ast::ImportNode {
+                    path: quoted[1..quoted.len() - 1].into(),
+                    start: token.start,
+                });
             }
             TokenKind::EOF => break,
-            _ => abort_with_message!(token, input, "expected `extern` or `fn`"),
+            _ => abort_with_message!(
+                token,
+                input,
+                "expected `extern`, `fn`, `const`, `static`, `enum`, `trait`, `impl`, `pub`, `test` or `import`"
+            ),
         }
     }
     ast
 }
 fn parse_prototype(input: &mut CursoredFile, definition: bool) -> Proto {
     let name_token = next_token(input);
-    let TokenKind::Identifier(name) = name_token.kind else { 
+    let TokenKind::Identifier(ref name) = name_token.kind else { 
         abort_with_message!(name_token, input, "expected function's name in it's definition")
     };
+    if definition && name.contains("::") {
+        abort_with_message!(name_token, input, "functions are defined without the module's name");
+    }
 
-    let paren_token = next_token(input);
+    let less = TokenKind::Operator(OperatorKind::Binary(BinaryOperatorKind::Comparision(ComparisionOpKind::Less)));
+    let mut generics = Vec::new();
+    let mut paren_token = next_token(input);
+    if definition && paren_token.kind == less {
+        generics = parse_names(input, OperatorKind::Binary(BinaryOperatorKind::Comparision(ComparisionOpKind::Bigger)), ">");
+        paren_token = next_token(input);
+    }
     match paren_token.kind {
         TokenKind::Operator(OperatorKind::LParen) => (),
+        _ if definition => abort_with_message!(paren_token, input, "expected `(` or `<`"),
         _ => abort_with_message!(paren_token, input, "expected `(`"),
     }
 
     let mut args = Vec::<Ast>::new();
+    let mut types = Vec::new();
     let mut t = next_token(input);
 
     while t.kind != TokenKind::Operator(OperatorKind::RParen) {
         match t.kind {
-            TokenKind::Identifier(_) if name == "main" => abort_with_message!(t, input, "main function accepts no parameters"),
             TokenKind::Identifier(param) if definition => args.push(Ast::IdentifierNode(param)),
             _ if !definition => {
                 input.move_back_cursor(t.len);
@@ -49,7 +107,18 @@ fn parse_prototype(input: &mut CursoredFile, definition: bool) -> Proto {
             }
             _ => abort_with_message!(t, input, "expected an identifier"),
         }
-        let next = next_token(input);
+        let mut next = next_token(input);
+        if definition {
+            let annotated = next.kind == TokenKind::Operator(OperatorKind::Colon);
+            let ty = annotated.then(|| parse_type(input));
+            if let Some(Type::Tuple(_)) = ty {
+                abort_with_message!(next, input, "parameters cannot be tuples");
+            }
+            types.push(ty);
+            if annotated {
+                next = next_token(input);
+            }
+        }
         match next.kind {
             TokenKind::Operator(OperatorKind::Coma) => {
                 t = next_token(input);
@@ -59,12 +128,30 @@ fn parse_prototype(input: &mut CursoredFile, definition: bool) -> Proto {
             _ => abort_with_message!(next, input, "expected `,` or `)`"),
         }
     }
-    let semicol = next_token(input);
+    let mut returns = None;
+    let mut semicol = next_token(input);
+    if definition && semicol.kind == TokenKind::Operator(OperatorKind::Arrow) {
+        let ty = parse_type(input);
+        match ty {
+            Type::Reference { .. } => abort_with_message!(semicol, input, "functions cannot return references"),
+            Type::Function(_) => abort_with_message!(semicol, input, "only parameters can be typed `fn(..)`"),
+            _ => (),
+        }
+        returns = Some(ty);
+        semicol = next_token(input);
+    }
     match semicol.kind {
         TokenKind::Operator(OperatorKind::Semicol) if definition => (),
         _ => input.move_back_cursor(semicol.len),
     }
-    Proto { name, args }
+    Proto {
+        name: name.clone(),
+        args,
+        generics,
+        types,
+        returns,
+        start: name_token.start,
+    }
 }
 fn parse_block(input: &mut CursoredFile) -> Vec<Ast> {
     let curly = next_token(input);
@@ -75,20 +162,29 @@ fn parse_block(input: &mut CursoredFile) -> Vec<Ast> {
     loop {
         let t = next_token(input);
         match t.kind {
-            TokenKind::Keyword(KeywordKind::If) => body.push(Ast::IfNode {
-                condition: Box::new(parse_expression(input)),
-                body: parse_block(input),
-            }),
+            TokenKind::Keyword(KeywordKind::If) => body.push(parse_if(input, t.start)),
             TokenKind::Keyword(KeywordKind::While) => body.push(Ast::WhileNode {
                 condition: Box::new(parse_expression(input)),
                 body: parse_block(input),
+                start: t.start,
             }),
-            TokenKind::Keyword(KeywordKind::Let) => body.push(parse_let_expr(input)),
-            TokenKind::Identifier(_) | TokenKind::Literal(_) | TokenKind::Operator(OperatorKind::LParen) => {
+            TokenKind::Keyword(KeywordKind::For) => body.push(parse_for(input, t.start)),
+            TokenKind::Keyword(KeywordKind::Match) => body.push(parse_match(input, t.start)),
+            TokenKind::Keyword(KeywordKind::Let) => body.push(parse_let_expr(input, t.start)),
+            TokenKind::Identifier(_)
+            | TokenKind::Literal(_)
+            | TokenKind::Operator(OperatorKind::LParen | OperatorKind::Binary(BinaryOperatorKind::Multiplication)) => {
                 input.move_back_cursor(t.len);
                 body.push(parse_expression(input));
             }
-            TokenKind::Keyword(KeywordKind::Ret) => body.push(Ast::RetNode(Box::new(parse_expression(input)))),
+            TokenKind::Keyword(KeywordKind::Ret) => body.push(Ast::RetNode {
+                value: Box::new(parse_expression(input)),
+                start: t.start,
+            }),
+            TokenKind::Keyword(KeywordKind::Defer) => body.push(Ast::DeferNode {
+                value: Box::new(parse_expression(input)),
+                start: t.start,
+            }),
             TokenKind::Operator(OperatorKind::RCurly) => break,
             _ => abort_with_message!(t, input, "expected `}` or an expression"),
         }
@@ -96,55 +192,558 @@ fn parse_block(input: &mut CursoredFile) -> Vec<Ast> {
     body
 }
 fn parse_expression(input: &mut CursoredFile) -> Ast {
-    let ast = fetch_lhs(input);
+    let ast = parse_binary(input, 0);
     let token = next_token(input);
-    if let TokenKind::Operator(op) = &token.kind {
-        match op {
-            OperatorKind::Binary(BinaryOperatorKind::Assigment) if matches!(ast, Ast::ValueNode(_)) => {
-                abort_with_message!(token, input, format!("function parameters are immutable"))
-            }
-            OperatorKind::Binary(op) => Ast::BinaryNode {
-                left: Box::new(ast),
-                right: Box::new(parse_expression(input)),
-                op: *op,
-            },
-            OperatorKind::Semicol => ast,
-            _ => {
+    if token.kind != TokenKind::Operator(OperatorKind::Semicol) {
+        input.move_back_cursor(token.len);
+    }
+    ast
+}
+/// Whether only whitespace precedes `start` on its line
+fn starts_line(input: &CursoredFile, start: usize) -> bool {
+    input.content[..start].iter().rev().take_while(|c| **c != '\n').all(|c| c.is_whitespace())
+}
+/// Whether the `*` just read starts a line assigning through a reference, like `*x = 1` or `*x += 1`, which is the
+/// next statement rather than a multiplication
+fn assigns_through_reference(input: &mut CursoredFile, star: &Token) -> bool {
+    if !starts_line(input, star.start) {
+        return false;
+    }
+    let cursor = input.get_cursor();
+    let name = next_token(input);
+    let op = next_token(input);
+    input.cursor.replace(cursor);
+    matches!(name.kind, TokenKind::Identifier(_))
+        && matches!(
+            op.kind,
+            TokenKind::Operator(OperatorKind::CompoundAssigment(_) | OperatorKind::Binary(BinaryOperatorKind::Assigment))
+        )
+}
+/// Parses operators that bind at least as tightly as `min_precedence` with precedence climbing
+fn parse_binary(input: &mut CursoredFile, min_precedence: u8) -> Ast {
+    let mut ast = fetch_lhs(input);
+    loop {
+        let token = next_token(input);
+        if let TokenKind::Operator(OperatorKind::CompoundAssigment(op)) = token.kind {
+            if min_precedence > 1 {
                 input.move_back_cursor(token.len);
-                ast
+                return ast;
             }
+            if !matches!(ast, Ast::IdentifierNode(_) | Ast::DerefNode { .. } | Ast::IndexNode { .. }) {
+                abort_with_message!(token, input, "only variables can be assigned to")
+            }
+            // Binds as loosely as `=`
+            return Ast::CompoundAssignNode {
+                assignee: Box::new(ast),
+                value: Box::new(parse_binary(input, 1)),
+                op,
+                start: token.start,
+            };
         }
-    } else {
-        input.move_back_cursor(token.len);
-        ast
+        let TokenKind::Operator(OperatorKind::Binary(op)) = token.kind else {
+            input.move_back_cursor(token.len);
+            return ast;
+        };
+        let (precedence, right_associative) = precedence(op);
+        if precedence < min_precedence || op == BinaryOperatorKind::Multiplication && assigns_through_reference(input, &token) {
+            input.move_back_cursor(token.len);
+            return ast;
+        }
+        if op == BinaryOperatorKind::Assigment && matches!(ast, Ast::ValueNode(_)) {
+            abort_with_message!(token, input, format!("function parameters are immutable"))
+        }
+        if op == BinaryOperatorKind::Assigment && !matches!(ast, Ast::IdentifierNode(_) | Ast::DerefNode { .. } | Ast::IndexNode { .. }) {
+            abort_with_message!(token, input, "only variables can be assigned to")
+        }
+        let right = parse_binary(input, if right_associative { precedence } else { precedence + 1 });
+        ast = Ast::BinaryNode {
+            left: Box::new(ast),
+            right: Box::new(right),
+            op,
+            start: token.start,
+        };
     }
 }
-fn parse_unit_expr(input: &mut CursoredFile) -> Ast {
-    let ast = fetch_lhs(input);
-    let token = next_token(input);
-    match token.kind {
-        TokenKind::Operator(op) => match op {
-            OperatorKind::Binary(op) => Ast::BinaryNode {
-                left: Box::new(ast),
-                right: Box::new(parse_unit_expr(input)),
-                op,
-            },
-            OperatorKind::RParen => ast,
+/// How tightly the operator binds, and whether `a op b op c` means `a op (b op c)`
+fn precedence(op: BinaryOperatorKind) -> (u8, bool) {
+    match op {
+        BinaryOperatorKind::Assigment => (1, true),
+        BinaryOperatorKind::Comparision(_) => (2, false),
+        BinaryOperatorKind::Addition | BinaryOperatorKind::Subtraction => (3, false),
+        BinaryOperatorKind::Multiplication
+        | BinaryOperatorKind::Division
+        | BinaryOperatorKind::Remainder
+        | BinaryOperatorKind::FloorDivision => (4, false),
+        BinaryOperatorKind::Power => (5, true),
+    }
+}
+/// Parses an expression in parentheses after the `(`, or a tuple when there are several separated by commas
+fn parse_unit_expr(input: &mut CursoredFile, start: usize) -> Ast {
+    let mut elements = vec![parse_binary(input, 0)];
+    loop {
+        let token = next_token(input);
+        match token.kind {
+            TokenKind::Operator(OperatorKind::RParen) => break,
+            TokenKind::Operator(OperatorKind::Coma) => elements.push(parse_binary(input, 0)),
+            _ if elements.len() > 1 => abort_with_message!(token, input, "expected a binary operator, `,` or `)`"),
             _ => abort_with_message!(token, input, "expected a binary operator or `)`"),
-        },
-        _ => abort_with_message!(token, input, "expected `)`"),
+        }
+    }
+    if elements.len() == 1 {
+        return Ast::UnitNode(Box::new(elements.pop().unwrap()));
     }
+    Ast::TupleNode { elements, start }
 }
 fn fetch_lhs(input: &mut CursoredFile) -> Ast {
     let lhs_token = next_token(input);
-    match lhs_token.kind {
+    let lhs = match lhs_token.kind {
         TokenKind::Identifier(_) => {
             input.move_back_cursor(lhs_token.len); // todo: !!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!
             fetch_ident_or_call(input)
         }
         TokenKind::Literal(l) => Ast::ValueNode(l),
-        TokenKind::Operator(OperatorKind::LParen) => Ast::UnitNode(Box::new(parse_unit_expr(input))),
+        TokenKind::Operator(OperatorKind::LParen) => parse_unit_expr(input, lhs_token.start),
+        TokenKind::Keyword(KeywordKind::If) => {
+            let ast = parse_if(input, lhs_token.start);
+            let Ast::IfNode { body, else_body, .. } = &ast else { unreachable!() };
+            let Some(else_body) = else_body else {
+                abort_with_message!(lhs_token, input, "`if` used as a value needs an `else`")
+            };
+            for (block, branch) in [(body, "`if`"), (else_body, "`else`")] {
+                if block.iter().any(|ast| matches!(ast, Ast::RetNode { .. })) {
+                    abort_with_message!(lhs_token, input, format!("cannot `ret` from the {branch} branch of an `if` used as a value"))
+                }
+                if !block.last().is_some_and(Ast::produces_value) {
+                    abort_with_message!(lhs_token, input, format!("the {branch} branch produces no value"))
+                }
+            }
+            ast
+        }
+        TokenKind::Keyword(KeywordKind::Match) => {
+            let ast = parse_match(input, lhs_token.start);
+            let Ast::MatchNode { arms, .. } = &ast else { unreachable!() };
+            for arm in arms {
+                if arm.body.iter().any(|ast| matches!(ast, Ast::RetNode { .. })) {
+                    abort_with_message!(lhs_token, input, "cannot `ret` from an arm of a `match` used as a value")
+                }
+                if !arm.body.last().is_some_and(Ast::produces_value) {
+                    abort_with_message!(lhs_token, input, "an arm of the `match` produces no value")
+                }
+            }
+            ast
+        }
+        TokenKind::Operator(OperatorKind::LBracket) => parse_vector(input, lhs_token.start),
+        TokenKind::Operator(OperatorKind::Pipe) => return parse_closure(input, lhs_token.start),
+        TokenKind::Operator(OperatorKind::Ampersand) => {
+            let mut token = next_token(input);
+            let mutable = token.kind == TokenKind::Keyword(KeywordKind::Mut);
+            if mutable {
+                token = next_token(input);
+            }
+            let TokenKind::Identifier(name) = token.kind else {
+                abort_with_message!(token, input, "expected the name of a variable")
+            };
+            return Ast::ReferenceNode {
+                name,
+                mutable,
+                start: lhs_token.start,
+            };
+        }
+        TokenKind::Operator(OperatorKind::Binary(BinaryOperatorKind::Multiplication)) => {
+            let token = next_token(input);
+            let TokenKind::Identifier(name) = token.kind else {
+                abort_with_message!(token, input, "expected the name of a reference")
+            };
+            return Ast::DerefNode {
+                name,
+                start: lhs_token.start,
+            };
+        }
         _ => abort_with_message!(lhs_token, input, "expected an identifier or literal"),
+    };
+    parse_postfix(input, lhs)
+}
+/// Parses the elements of a vector after the `[`
+fn parse_vector(input: &mut CursoredFile, start: usize) -> Ast {
+    let mut elements = Vec::new();
+    let mut token = next_token(input);
+    while token.kind != TokenKind::Operator(OperatorKind::RBracket) {
+        input.move_back_cursor(token.len);
+        elements.push(parse_binary(input, 0));
+        let next = next_token(input);
+        match next.kind {
+            TokenKind::Operator(OperatorKind::Coma) => token = next_token(input),
+            TokenKind::Operator(OperatorKind::RBracket) => break,
+            _ => abort_with_message!(next, input, "expected `,` or `]`"),
+        }
+    }
+    Ast::VectorNode { elements, start }
+}
+/// Parses any number of `[index]` and `.method(args)` following `value`
+fn parse_postfix(input: &mut CursoredFile, mut value: Ast) -> Ast {
+    loop {
+        let token = next_token(input);
+        match token.kind {
+            TokenKind::Operator(OperatorKind::LBracket) => {
+                let index = parse_binary(input, 0);
+                let bracket = next_token(input);
+                if bracket.kind != TokenKind::Operator(OperatorKind::RBracket) {
+                    abort_with_message!(bracket, input, "expected a binary operator or `]`");
+                }
+                value = Ast::IndexNode {
+                    vector: Box::new(value),
+                    index: Box::new(index),
+                    start: token.start,
+                };
+            }
+            TokenKind::Operator(OperatorKind::Dot) => {
+                let method = next_token(input);
+                match method.kind {
+                    TokenKind::Identifier(ref name) if name.contains("::") => {
+                        abort_with_message!(method, input, "methods are called without the trait's name")
+                    }
+                    TokenKind::Identifier(_) => input.move_back_cursor(method.len),
+                    _ => abort_with_message!(method, input, "expected the name of a method"),
+                }
+                let Ast::CallNode(proto) = fetch_ident_or_call(input) else {
+                    abort_with_message!(method, input, "expected `(` after the name of the method")
+                };
+                value = Ast::MethodNode {
+                    receiver: Box::new(value),
+                    method: proto.name,
+                    args: proto.args,
+                    start: token.start,
+                };
+            }
+            _ => {
+                input.move_back_cursor(token.len);
+                return value;
+            }
+        }
+    }
+}
+/// Parses `|params| body` after the first `|`. The body takes in everything up to the end of the expression
+fn parse_closure(input: &mut CursoredFile, start: usize) -> Ast {
+    Ast::ClosureNode {
+        params: parse_names(input, OperatorKind::Pipe, "|"),
+        body: Box::new(parse_binary(input, 0)),
+        start,
+    }
+}
+/// Parses names separated by commas up to `closing`, which is spelled `symbol`
+fn parse_names(input: &mut CursoredFile, closing: OperatorKind, symbol: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut token = next_token(input);
+    while token.kind != TokenKind::Operator(closing) {
+        let TokenKind::Identifier(ref name) = token.kind else {
+            abort_with_message!(token, input, "expected an identifier")
+        };
+        if name.contains("::") {
+            abort_with_message!(token, input, "names cannot contain `::`");
+        }
+        names.push(name.clone());
+        let next = next_token(input);
+        match next.kind {
+            TokenKind::Operator(OperatorKind::Coma) => token = next_token(input),
+            kind if kind == TokenKind::Operator(closing) => break,
+            _ => abort_with_message!(next, input, format!("expected `,` or `{symbol}`")),
+        }
+    }
+    names
+}
+/// Parses the type after a `:` or `->`. A type is `f64`, a type parameter standing for it or an enum, a tuple of them or
+/// a reference `&T`/`&mut T` to one. The code generation checks that the names are known
+fn parse_type(input: &mut CursoredFile) -> Type {
+    let token = next_token(input);
+    match token.kind {
+        TokenKind::Operator(OperatorKind::LParen) => {
+            let mut types = Vec::new();
+            loop {
+                match parse_type(input) {
+                    Type::Tuple(_) => abort_with_message!(token, input, "tuples cannot be nested"),
+                    Type::Reference { .. } => abort_with_message!(token, input, "tuples cannot hold references"),
+                    Type::Function(_) => abort_with_message!(token, input, "tuples cannot hold functions"),
+                    ty => types.push(ty),
+                }
+                let next = next_token(input);
+                match next.kind {
+                    TokenKind::Operator(OperatorKind::Coma) => (),
+                    TokenKind::Operator(OperatorKind::RParen) => break,
+                    _ => abort_with_message!(next, input, "expected `,` or `)`"),
+                }
+            }
+            if types.len() < 2 {
+                abort_with_message!(token, input, "a tuple has at least two elements");
+            }
+            Type::Tuple(types)
+        }
+        TokenKind::Operator(OperatorKind::Ampersand) => {
+            let next = next_token(input);
+            let mutable = next.kind == TokenKind::Keyword(KeywordKind::Mut);
+            if !mutable {
+                input.move_back_cursor(next.len);
+            }
+            match parse_type(input) {
+                Type::Name(name) => Type::Reference { mutable, ty: Box::new(Type::Name(name)) },
+                _ => abort_with_message!(token, input, "references can only point to numbers"),
+            }
+        }
+        TokenKind::Keyword(KeywordKind::Fn) => {
+            let paren = next_token(input);
+            if paren.kind != TokenKind::Operator(OperatorKind::LParen) {
+                abort_with_message!(paren, input, "expected `(`");
+            }
+            let mut params = Vec::new();
+            let mut next = next_token(input);
+            while next.kind != TokenKind::Operator(OperatorKind::RParen) {
+                input.move_back_cursor(next.len);
+                match parse_type(input) {
+                    ty @ Type::Name(_) => params.push(ty),
+                    _ => abort_with_message!(token, input, "functions held by values only take numbers"),
+                }
+                next = next_token(input);
+                match next.kind {
+                    TokenKind::Operator(OperatorKind::Coma) => next = next_token(input),
+                    TokenKind::Operator(OperatorKind::RParen) => (),
+                    _ => abort_with_message!(next, input, "expected `,` or `)`"),
+                }
+            }
+            Type::Function(params)
+        }
+        TokenKind::Identifier(ref name) => Type::Name(name.clone()),
+        _ => abort_with_message!(token, input, "expected a type"),
+    }
+}
+/// Parses `match value { pattern => body, ... }` after the `match`
+fn parse_match(input: &mut CursoredFile, start: usize) -> Ast {
+    let value = parse_binary(input, 0);
+    let curly = next_token(input);
+    if curly.kind != TokenKind::Operator(OperatorKind::LCurly) {
+        abort_with_message!(curly, input, "expected `{`");
+    }
+    let mut arms = Vec::new();
+    loop {
+        let token = next_token(input);
+        if token.kind == TokenKind::Operator(OperatorKind::RCurly) {
+            if arms.is_empty() {
+                abort_with_message!(token, input, "expected a pattern");
+            }
+            break;
+        }
+        input.move_back_cursor(token.len);
+        let pattern = parse_pattern(input);
+        let arrow = next_token(input);
+        if arrow.kind != TokenKind::Operator(OperatorKind::FatArrow) {
+            abort_with_message!(arrow, input, "expected `=>`");
+        }
+        let next = next_token(input);
+        input.move_back_cursor(next.len);
+        let expression = next.kind != TokenKind::Operator(OperatorKind::LCurly);
+        let body = if expression { vec![parse_binary(input, 0)] } else { parse_block(input) };
+        arms.push(Arm {
+            pattern,
+            body,
+            expression,
+            start: token.start,
+        });
+        // Only arms with blocks can go without a comma
+        let separator = next_token(input);
+        match separator.kind {
+            TokenKind::Operator(OperatorKind::Coma) => (),
+            TokenKind::Operator(OperatorKind::RCurly) => break,
+            _ if !expression => input.move_back_cursor(separator.len),
+            _ => abort_with_message!(separator, input, "expected `,` or `}`"),
+        }
+    }
+    Ast::MatchNode {
+        value: Box::new(value),
+        arms,
+        start,
+    }
+}
+fn parse_pattern(input: &mut CursoredFile) -> Pattern {
+    let token = next_token(input);
+    if matches!(
+        token.kind,
+        TokenKind::Literal(LiteralKind::Num(_)) | TokenKind::Operator(OperatorKind::Binary(BinaryOperatorKind::Subtraction))
+    ) {
+        input.move_back_cursor(token.len);
+        let from = parse_pattern_number(input);
+        let range = next_token(input);
+        let inclusive = match range.kind {
+            TokenKind::Operator(OperatorKind::Range) => false,
+            TokenKind::Operator(OperatorKind::RangeInclusive) => true,
+            _ => {
+                input.move_back_cursor(range.len);
+                return Pattern::Number(from);
+            }
+        };
+        return Pattern::Range {
+            from,
+            to: parse_pattern_number(input),
+            inclusive,
+        };
+    }
+    let TokenKind::Identifier(ref name) = token.kind else {
+        abort_with_message!(token, input, "expected a pattern")
+    };
+    if name == "_" {
+        return Pattern::Wildcard;
+    }
+    let name = name.clone();
+    let paren = next_token(input);
+    if paren.kind != TokenKind::Operator(OperatorKind::LParen) {
+        input.move_back_cursor(paren.len);
+        return Pattern::Variant { name, bindings: Vec::new() };
+    }
+    Pattern::Variant {
+        name,
+        bindings: parse_names(input, OperatorKind::RParen, ")"),
+    }
+}
+fn parse_pattern_number(input: &mut CursoredFile) -> f64 {
+    let mut token = next_token(input);
+    let negative = token.kind == TokenKind::Operator(OperatorKind::Binary(BinaryOperatorKind::Subtraction));
+    if negative {
+        token = next_token(input);
+    }
+    let TokenKind::Literal(LiteralKind::Num(n)) = token.kind else {
+        abort_with_message!(token, input, "expected a number")
+    };
+    if negative { -n } else { n }
+}
+/// Parses `enum name { variants }` after the `enum`
+fn parse_enum(input: &mut CursoredFile, start: usize, public: bool) -> Ast {
+    let name_token = next_token(input);
+    let TokenKind::Identifier(ref name) = name_token.kind else {
+        abort_with_message!(name_token, input, "expected an identifier")
+    };
+    if name.contains("::") {
+        abort_with_message!(name_token, input, "enums are defined without the module's name");
+    }
+    let name = name.clone();
+    let curly = next_token(input);
+    if curly.kind != TokenKind::Operator(OperatorKind::LCurly) {
+        abort_with_message!(curly, input, "expected `{`");
+    }
+    let mut variants: Vec<Variant> = Vec::new();
+    loop {
+        let token = next_token(input);
+        if token.kind == TokenKind::Operator(OperatorKind::RCurly) {
+            break;
+        }
+        let TokenKind::Identifier(ref variant) = token.kind else {
+            abort_with_message!(token, input, "expected a variant")
+        };
+        if variant.contains("::") {
+            abort_with_message!(token, input, "names cannot contain `::`");
+        }
+        if variants.iter().any(|v| v.name == *variant) {
+            abort_with_message!(token, input, format!("variant `{variant}` is already defined"));
+        }
+        let variant = variant.clone();
+        let paren = next_token(input);
+        let fields = if paren.kind == TokenKind::Operator(OperatorKind::LParen) {
+            parse_names(input, OperatorKind::RParen, ")")
+        } else {
+            input.move_back_cursor(paren.len);
+            Vec::new()
+        };
+        variants.push(Variant {
+            name: variant,
+            fields,
+            start: token.start,
+        });
+        let separator = next_token(input);
+        match separator.kind {
+            TokenKind::Operator(OperatorKind::Coma) => (),
+            TokenKind::Operator(OperatorKind::RCurly) => break,
+            _ => abort_with_message!(separator, input, "expected `,` or `}`"),
+        }
+    }
+    if variants.is_empty() {
+        abort_with_message!(name_token, input, "an enum needs at least one variant");
+    }
+    Ast::EnumNode {
+        name,
+        variants,
+        public,
+        start,
+    }
+}
+/// Parses `trait name { prototypes }` after the `trait`
+fn parse_trait(input: &mut CursoredFile, start: usize, public: bool) -> Ast {
+    let name_token = next_token(input);
+    let TokenKind::Identifier(ref name) = name_token.kind else {
+        abort_with_message!(name_token, input, "expected an identifier")
+    };
+    if name.contains("::") {
+        abort_with_message!(name_token, input, "traits are defined without the module's name");
+    }
+    Ast::TraitNode {
+        name: name.clone(),
+        methods: parse_methods(input, false).into_iter().map(|(proto, _)| proto).collect(),
+        public,
+        start,
+    }
+}
+/// Parses `impl name for target { functions }` after the `impl`
+fn parse_impl(input: &mut CursoredFile, start: usize) -> Ast {
+    let name_token = next_token(input);
+    let TokenKind::Identifier(ref name) = name_token.kind else {
+        abort_with_message!(name_token, input, "expected the name of a trait")
+    };
+    let name = name.clone();
+    let for_token = next_token(input);
+    if for_token.kind != TokenKind::Keyword(KeywordKind::For) {
+        abort_with_message!(for_token, input, "expected `for`");
+    }
+    let target_token = next_token(input);
+    let TokenKind::Identifier(ref target) = target_token.kind else {
+        abort_with_message!(target_token, input, "expected `f64` or the name of an enum")
+    };
+    Ast::ImplNode {
+        name,
+        target: target.clone(),
+        functions: parse_methods(input, true)
+            .into_iter()
+            .map(|(proto, body)| Ast::FunctionNode {
+                proto,
+                body: body.unwrap(),
+                public: false,
+            })
+            .collect(),
+        start,
+    }
+}
+/// Parses the `fn`s between the curly braces of a `trait`, or with their `bodies` of an `impl`
+fn parse_methods(input: &mut CursoredFile, bodies: bool) -> Vec<(Proto, Option<Vec<Ast>>)> {
+    let curly = next_token(input);
+    if curly.kind != TokenKind::Operator(OperatorKind::LCurly) {
+        abort_with_message!(curly, input, "expected `{`");
+    }
+    let mut methods = Vec::new();
+    loop {
+        let fn_token = next_token(input);
+        match fn_token.kind {
+            TokenKind::Operator(OperatorKind::RCurly) => return methods,
+            TokenKind::Keyword(KeywordKind::Fn) => (),
+            _ => abort_with_message!(fn_token, input, "expected `fn` or `}`"),
+        }
+        let proto = parse_prototype(input, true);
+        if !proto.generics.is_empty() {
+            abort_with_message!(fn_token, input, "methods cannot be generic");
+        }
+        if proto.results() > 1 {
+            abort_with_message!(fn_token, input, "methods cannot return tuples");
+        }
+        if proto.references().iter().any(Option::is_some) {
+            abort_with_message!(fn_token, input, "methods cannot take references");
+        }
+        if proto.args.first() != Some(&Ast::IdentifierNode("self".into())) {
+            abort_with_message!(fn_token, input, "methods take `self` first");
+        }
+        methods.push((proto, bodies.then(|| parse_block(input))));
     }
 }
 fn fetch_ident_or_call(input: &mut CursoredFile) -> Ast {
@@ -161,19 +760,140 @@ fn fetch_ident_or_call(input: &mut CursoredFile) -> Ast {
     input.move_back_cursor(name_token.len);
     Ast::CallNode(parse_prototype(input, false))
 }
-fn parse_let_expr(input: &mut CursoredFile) -> Ast {
+fn parse_let_expr(input: &mut CursoredFile, start: usize) -> Ast {
     let token = next_token(input);
     match token.kind {
+        TokenKind::Operator(OperatorKind::LParen) => {
+            let names = parse_names(input, OperatorKind::RParen, ")");
+            if names.len() < 2 {
+                abort_with_message!(token, input, "a tuple has at least two elements");
+            }
+            let token = next_token(input);
+            if token.kind != TokenKind::Operator(OperatorKind::Binary(BinaryOperatorKind::Assigment)) {
+                abort_with_message!(token, input, "expected `=`");
+            }
+            Ast::DestructureNode {
+                names,
+                value: Box::new(parse_expression(input)),
+                start,
+            }
+        }
         TokenKind::Identifier(assignee) => {
             let token = next_token(input);
             match token.kind {
                 TokenKind::Operator(OperatorKind::Binary(BinaryOperatorKind::Assigment)) => Ast::LetNode {
                     assignee,
                     value: Box::new(parse_expression(input)),
+                    start,
                 },
                 _ => abort_with_message!(token, input, "expected `=`"),
             }
         }
         _ => abort_with_message!(token, input, "expected an identifier"),
     }
-}
\ No newline at end of file
+}
+fn parse_for(input: &mut CursoredFile, start: usize) -> Ast {
+    let token = next_token(input);
+    let TokenKind::Identifier(variable) = token.kind else {
+        abort_with_message!(token, input, "expected an identifier")
+    };
+    let token = next_token(input);
+    if token.kind != TokenKind::Keyword(KeywordKind::In) {
+        abort_with_message!(token, input, "expected `in`");
+    }
+    let from = parse_expression(input);
+    let token = next_token(input);
+    let inclusive = match token.kind {
+        TokenKind::Operator(OperatorKind::Range) => false,
+        TokenKind::Operator(OperatorKind::RangeInclusive) => true,
+        TokenKind::Operator(OperatorKind::LCurly) => {
+            input.move_back_cursor(token.len);
+            return Ast::ForEachNode {
+                variable,
+                vector: Box::new(from),
+                body: parse_block(input),
+                start,
+            };
+        }
+        _ => abort_with_message!(token, input, "expected `..`, `..=` or `{`"),
+    };
+    let to = parse_expression(input);
+    let token = next_token(input);
+    let step = if token.kind == TokenKind::Keyword(KeywordKind::Step) {
+        let step = parse_expression(input);
+        if step == Ast::ValueNode(LiteralKind::Num(0.0)) {
+            abort_with_message!(token, input, "the step cannot be 0, the loop would never end");
+        }
+        Some(Box::new(step))
+    } else {
+        input.move_back_cursor(token.len);
+        None
+    };
+    Ast::ForNode {
+        variable,
+        from: Box::new(from),
+        to: Box::new(to),
+        inclusive,
+        step,
+        body: parse_block(input),
+        start,
+    }
+}
+fn parse_if(input: &mut CursoredFile, start: usize) -> Ast {
+    let condition = Box::new(parse_expression(input));
+    let body = parse_block(input);
+    let token = next_token(input);
+    let else_body = if token.kind == TokenKind::Keyword(KeywordKind::Else) {
+        let token = next_token(input);
+        if token.kind == TokenKind::Keyword(KeywordKind::If) {
+            Some(vec![parse_if(input, token.start)])
+        } else {
+            input.move_back_cursor(token.len);
+            Some(parse_block(input))
+        }
+    } else {
+        input.move_back_cursor(token.len);
+        None
+    };
+    Ast::IfNode {
+        condition,
+        body,
+        else_body,
+        start,
+    }
+}
+/// Parses a function, `const`, `static` or `enum` that starts with `keyword`
+fn parse_item(input: &mut CursoredFile, keyword: &Token, public: bool) -> Ast {
+    if keyword.kind == TokenKind::Keyword(KeywordKind::Enum) {
+        return parse_enum(input, keyword.start, public);
+    }
+    if keyword.kind == TokenKind::Keyword(KeywordKind::Trait) {
+        return parse_trait(input, keyword.start, public);
+    }
+    if keyword.kind == TokenKind::Keyword(KeywordKind::Fn) {
+        return Ast::FunctionNode {
+            proto: parse_prototype(input, true),
+            body: parse_block(input),
+            public,
+        };
+    }
+    let token = next_token(input);
+    let TokenKind::Identifier(ref name) = token.kind else {
+        abort_with_message!(token, input, "expected an identifier")
+    };
+    if name.contains("::") {
+        abort_with_message!(token, input, "globals are defined without the module's name");
+    }
+    let name = name.clone();
+    let token = next_token(input);
+    if token.kind != TokenKind::Operator(OperatorKind::Binary(BinaryOperatorKind::Assigment)) {
+        abort_with_message!(token, input, "expected `=`");
+    }
+    Ast::GlobalNode {
+        name,
+        value: Box::new(parse_expression(input)),
+        mutable: keyword.kind == TokenKind::Keyword(KeywordKind::Static),
+        public,
+        start: keyword.start,
+    }
+}
@@ -1,41 +1,274 @@
 mod bindings;
+mod debug;
 mod var;
 
 use std::collections::HashMap;
+use std::ops::Range;
+use std::path::Path;
 
-use bindings::{LLVMValueRef, LLVMWrapper};
+use bindings::{LLVMBasicBlockRef, LLVMTypeRef, LLVMValueRef, LLVMWrapper};
 use var::Variable;
 
-use crate::ast::{Ast, Proto};
-use crate::lexer::tokens::{BinaryOperatorKind, LiteralKind};
-use crate::{abort, abort_if_not};
+use crate::ast::{Arm, Ast, Pattern, Proto, Type};
+use crate::import::Module;
+use crate::input::{CursoredFile, TargetOptions};
+use crate::lexer::tokens::{BinaryOperatorKind, ComparisionOpKind, LiteralKind};
+use crate::{abort, abort_if_not, pass};
+
+/// Prefix of the symbols of the functions of the program, which keeps them apart from the C library ones LLVM calls
+const SYMBOL_PREFIX: &str = "pitusya::";
+
+/// Name of the functions closures are generated into, LLVM makes them unique
+const CLOSURE: &str = "pitusya.closure";
 
 pub struct Cg {
+    /// Declared functions. Instances of generic functions are named after the types they are generated for, like
+    /// `biggest<f64>`
     vtable: HashMap<String, LLVMValueRef>,
+    /// Generic functions, generated once for every combination of types they are called with
+    generics: HashMap<String, Definition>,
+    /// Instances of generic functions waiting to be generated
+    instances: Vec<Definition>,
+    /// Index of the module being declared
+    module: usize,
+    /// `const`s and `static`s, every function starts with them in scope
+    globals: HashMap<String, Variable>,
+    /// Values of the `const`s for evaluating other constant expressions
+    constants: HashMap<String, f64>,
     wrapper: LLVMWrapper,
     contains_main: bool,
+    tests: Vec<String>,
+    in_test: bool,
+    /// Tag, number of fields and type of the values of every variant of the enums. Tags are unique across enums
+    variants: HashMap<String, (usize, usize, LLVMTypeRef)>,
+    /// Tags of the variants of every enum, and the type of its values
+    enums: HashMap<String, (Range<usize>, LLVMTypeRef)>,
+    /// Methods of every trait and how many parameters they take
+    traits: HashMap<String, Vec<(String, usize)>>,
+    /// Methods of all the traits and how many parameters they take, values of the enums implementing them are called
+    /// with `.`
+    methods: HashMap<String, usize>,
+    /// Functions returning tuples and how many values are in them
+    tuples: HashMap<String, usize>,
+    /// How many values the function being generated returns
+    results: usize,
+    /// Functions taking references, and whether each parameter takes a mutable reference, a reference or a value
+    references: HashMap<String, Vec<Option<bool>>>,
+    /// Functions taking functions, and how many arguments each parameter typed `fn(..)` passes to its function
+    arities: HashMap<String, Vec<Option<usize>>>,
+    /// Expressions deferred in each block being generated with the variables in scope at their `defer`, the innermost
+    /// block last
+    deferred: Vec<Vec<(Ast, HashMap<String, Variable>)>>,
+    /// Closures whose bodies are generated once the current function is finished
+    closures: Vec<Closure>,
+    /// Name of the file the code is generated from, and where its lines start, for runtime errors
+    file: String,
+    lines: Vec<usize>,
+}
+/// A function generated after the modules, from the module at `module`
+struct Definition {
+    proto: Proto,
+    body: Vec<Ast>,
+    module: usize,
+}
+struct Closure {
+    function: LLVMValueRef,
+    params: Vec<String>,
+    /// Captured variables and the arities of the functions they hold
+    captures: Vec<(String, Option<usize>)>,
+    body: Ast,
+    start: usize,
+}
+/// How a test ended
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Passed,
+    /// The assertion at the position failed
+    Failed(usize),
+    /// The test exited or crashed before it finished
+    Stopped,
 }
 
 impl Cg {
-    pub fn codegen(&mut self, ast: Ast) {
+    pub fn new(options: &TargetOptions) -> Self {
+        Self {
+            vtable: HashMap::new(),
+            generics: HashMap::new(),
+            instances: Vec::new(),
+            module: 0,
+            globals: HashMap::new(),
+            constants: HashMap::new(),
+            wrapper: unsafe { LLVMWrapper::new(options) },
+            contains_main: false,
+            tests: Vec::new(),
+            in_test: false,
+            variants: HashMap::new(),
+            enums: HashMap::new(),
+            traits: HashMap::new(),
+            methods: HashMap::new(),
+            tuples: HashMap::new(),
+            results: 1,
+            references: HashMap::new(),
+            arities: HashMap::new(),
+            deferred: Vec::new(),
+            closures: Vec::new(),
+            file: String::new(),
+            lines: Vec::new(),
+        }
+    }
+    /// Checks the modules and generates them, once everything they define is declared
+    pub fn generate(&mut self, mut modules: Vec<Module>) {
+        let mut enums = pass::Enums::new();
+        for module in &mut modules {
+            pass::pipeline(&module.asts, &mut module.file, &mut enums);
+        }
+        // Functions may take the enums of any module, and implement the traits of any module
+        for order in 0..3 {
+            for (i, module) in modules.iter().enumerate() {
+                self.module = i;
+                self.set_source(&module.file);
+                module.asts.iter().filter(|ast| declaration_order(ast) == order).for_each(|ast| self.declare(ast));
+            }
+        }
+        let mut files = Vec::with_capacity(modules.len());
+        for module in modules {
+            self.set_source(&module.file);
+            module.asts.into_iter().for_each(|ast| self.codegen(ast));
+            files.push(module.file);
+        }
+        // Instances may call for more instances, which are generated too
+        while let Some(Definition { proto, body, module }) = self.instances.pop() {
+            self.set_source(&files[module]);
+            self.create_function(proto, body);
+        }
+    }
+    pub fn enable_debug_info(&mut self, source: &CursoredFile) {
+        unsafe { self.wrapper.enable_debug_info(source) }
+    }
+    /// Tells which file the code generated next comes from
+    pub fn set_source(&mut self, source: &CursoredFile) {
+        self.file = source.name.display().to_string();
+        self.lines = debug::line_starts(source);
+        unsafe { self.wrapper.set_source(source) }
+    }
+    /// Declares the enums, traits and functions of an item, so uses of them are known wherever they are. Every item
+    /// of every module is declared before any is generated, the enums first and then the traits
+    pub fn declare(&mut self, ast: &Ast) {
         match ast {
-            Ast::FunctionNode { proto, body } => {
+            Ast::FunctionNode { proto, body, .. } if !proto.generics.is_empty() => {
+                abort_if_not!(proto.name != "main", "main cannot be generic");
+                abort_if_not!(!self.defines(&proto.name), "Function {} already exists!", proto.name);
+                // The types are checked before any call instantiates the function
+                for ty in proto.types.iter().chain([&proto.returns]) {
+                    self.resolve_type(ty.as_ref(), &proto.generics);
+                }
+                self.remember_shape(&proto.name, proto);
+                let definition = Definition {
+                    proto: proto.clone(),
+                    body: body.clone(),
+                    module: self.module,
+                };
+                self.generics.insert(proto.name.clone(), definition);
+            }
+            Ast::FunctionNode { proto, .. } => {
+                let function = self.declare_function(proto);
                 if &proto.name == "main" {
+                    abort_if_not!(proto.results() == 1, "main cannot return a tuple");
+                    abort_if_not!(proto.references().iter().all(Option::is_none), "main cannot take references");
+                    abort_if_not!(self.takes_numbers(function), "main takes and returns numbers");
                     self.contains_main = true;
                 }
-                abort_if_not!(!self.vtable.contains_key(&proto.name), "Function {} already exists!", proto.name);
-                self.create_function(proto, body);
+            }
+            Ast::TestNode { proto, .. } => {
+                self.declare_function(proto);
+                self.tests.push(proto.name.clone());
             }
             Ast::ExternNode(proto) => {
                 let f = unsafe { self.wrapper.declare_function(&proto.name, proto.args.len()) };
-                self.vtable.insert(proto.name, f);
+                let mut types = proto.types.iter().map(Option::as_ref).chain([proto.returns.as_ref()]);
+                abort_if_not!(
+                    types.all(|ty| self.resolve_type(ty, &[]) == unsafe { self.wrapper.number_type() }),
+                    "Extern function {} takes and returns numbers",
+                    proto.name
+                );
+                self.vtable.insert(proto.name.clone(), f);
+            }
+            Ast::EnumNode { name, variants, .. } => {
+                abort_if_not!(!self.enums.contains_key(name), "Enum {} already exists!", name);
+                let fields = variants.iter().map(|v| v.fields.len()).max().unwrap_or_default();
+                let ty = unsafe { self.wrapper.create_enum_type(name, fields) };
+                let first = self.variants.len();
+                self.enums.insert(name.clone(), (first..first + variants.len(), ty));
+                for (tag, variant) in (first..).zip(variants) {
+                    self.variants.insert(format!("{name}::{}", variant.name), (tag, variant.fields.len(), ty));
+                }
+            }
+            Ast::TraitNode { name, methods, .. } => {
+                abort_if_not!(!self.traits.contains_key(name), "Trait {} already exists!", name);
+                for proto in methods {
+                    let arity = *self.methods.entry(proto.name.clone()).or_insert(proto.args.len());
+                    abort_if_not!(
+                        arity == proto.args.len(),
+                        "Method {} of {} takes {} parameters, but another trait declares it with {}",
+                        proto.name,
+                        name,
+                        proto.args.len(),
+                        arity
+                    );
+                }
+                self.traits.insert(name.clone(), methods.iter().map(|proto| (proto.name.clone(), proto.args.len())).collect());
+            }
+            Ast::ImplNode { name, target, functions, .. } => self.declare_impl(name, target, functions),
+            _ => (),
+        }
+    }
+    pub fn codegen(&mut self, ast: Ast) {
+        match ast {
+            // Generic functions are generated for the calls to them
+            Ast::FunctionNode { proto, .. } if !proto.generics.is_empty() => (),
+            Ast::FunctionNode { proto, body, .. } => self.create_function(proto, body),
+            Ast::TestNode { proto, mut body } => {
+                // Tests return zero when passed, and the position of the failed assertion otherwise
+                body.push(Ast::RetNode {
+                    value: Box::new(Ast::ValueNode(LiteralKind::Num(0.0))),
+                    start: proto.start,
+                });
+                self.in_test = true;
+                self.create_function(proto, body);
+                self.in_test = false;
+            }
+            Ast::ExternNode(_) => (),
+            Ast::GlobalNode { name, value, mutable, .. } => {
+                abort_if_not!(!self.globals.contains_key(&name), "Global {} already exists!", name);
+                let Some(initial) = self.evaluate(&value) else {
+                    abort!("The value of {} is not known at compile time", name)
+                };
+                let var = if mutable {
+                    Variable::new(unsafe { self.wrapper.create_global(&name, initial) }, false)
+                } else {
+                    self.constants.insert(name.clone(), initial);
+                    Variable::new(unsafe { self.wrapper.gen_fp(initial) }, true)
+                };
+                self.globals.insert(name, var);
+            }
+            Ast::ImplNode { target, functions, .. } => {
+                for function in functions {
+                    let Ast::FunctionNode { mut proto, body, .. } = function else {
+                        abort!("Please report how you have bypassed the parser")
+                    };
+                    proto.name = format!("{target}::{}", proto.name);
+                    self.create_function(proto, body);
+                }
             }
-            Ast::EOF => (),
+            Ast::EnumNode { .. } | Ast::TraitNode { .. } | Ast::ImportNode { .. } | Ast::EOF => (),
             _ => abort!("Please report how you have bypassed the parser"),
         }
     }
     #[allow(clippy::too_many_lines)]
     fn generate_ir(&mut self, ast: Ast, named_values: &mut HashMap<String, Variable>) -> LLVMValueRef {
+        if let Some(start) = ast.start() {
+            unsafe { self.wrapper.set_location(start) }
+        }
         match ast {
             Ast::ValueNode(literal) => {
                 let LiteralKind::Num(n) = literal else {
@@ -45,135 +278,1070 @@ impl Cg {
             }
             Ast::IdentifierNode(ident) => {
                 if let Some(var) = named_values.get(&ident) {
+                    abort_if_not!(var.reference.is_none(), "{} is a reference. Consider `*{}`", ident, ident);
                     var.value
+                } else if let Some(&(tag, fields, ty)) = self.variants.get(&ident) {
+                    abort_if_not!(fields == 0, "{} takes {} fields", ident, fields);
+                    unsafe { self.wrapper.create_variant(ty, tag, &[]) }
+                } else if self.generics.contains_key(&ident) {
+                    abort!("{ident} is generic and cannot be used as a value")
+                } else if let Some(function) = self.vtable.get(&ident) {
+                    abort_if_not!(!self.tuples.contains_key(&ident), "{} returns a tuple and cannot be used as a value", ident);
+                    abort_if_not!(!self.references.contains_key(&ident), "{} takes references and cannot be used as a value", ident);
+                    abort_if_not!(self.takes_numbers(*function), "{} takes or returns enums and cannot be used as a value", ident);
+                    unsafe { self.wrapper.function_value(*function) }
                 } else {
                     abort!("No variable {ident}. Consider creating it")
                 }
             }
-            Ast::LetNode { assignee, value } => {
-                let value = self.generate_ir(*value, named_values);
-                let value = unsafe { self.wrapper.create_var(value, &assignee) };
-                named_values.insert(assignee, Variable::new(value, false));
+            Ast::LetNode { assignee, value, start } => {
+                let arity = self.function_arity(&value, named_values);
+                let vector = self.holds_vector(&value, named_values) == Some(true);
+                let value = self.deref_or_generate(*value, named_values);
+                let value = unsafe { self.wrapper.create_var(value, &assignee, start) };
+                named_values.insert(assignee, Variable::new(value, false).with_arity(arity).with_vector(vector));
                 value
             }
-            Ast::CallNode(proto) => {
-                let function = if let Some(f) = self.vtable.get(&proto.name) {
-                    *f
-                } else {
-                    abort!("No function {}. Define it before calling", proto.name)
-                };
-
-                let argc = unsafe { self.wrapper.count_args(function) };
-                if argc != proto.args.len() {
-                    abort!(
+            Ast::CallNode(proto) if proto.name == "assert" => {
+                abort_if_not!(self.in_test, "assert can only be used in tests");
+                abort_if_not!(proto.args.len() == 1, "assert takes exactly one argument, but got {}", proto.args.len());
+                let start = proto.start;
+                let condition = self.number(proto.args.into_iter().next().unwrap(), "an assertion", named_values);
+                unsafe {
+                    let failure = self.wrapper.gen_fp(start as f64);
+                    self.wrapper.build_assert(condition, failure);
+                }
+                condition
+            }
+            Ast::CallNode(proto) if self.variants.contains_key(&proto.name) => {
+                let (tag, fields, ty) = self.variants[&proto.name];
+                abort_if_not!(
+                    fields == proto.args.len(),
+                    "Incorrect number of fields passed to {}. Expected {}, but got {}",
+                    proto.name,
+                    fields,
+                    proto.args.len()
+                );
+                let what = format!("a field of {}", proto.name);
+                let fields: Vec<LLVMValueRef> = proto.args.into_iter().map(|ast| self.number(ast, &what, named_values)).collect();
+                unsafe { self.wrapper.create_variant(ty, tag, &fields) }
+            }
+            Ast::CallNode(proto) if named_values.contains_key(&proto.name) => {
+                // The variable holds a function, and shadows the builtins named like it
+                let var = named_values[&proto.name];
+                if let Some(arity) = var.arity {
+                    abort_if_not!(
+                        arity == proto.args.len(),
                         "Incorrect number of arguments passed to {}. Expected {}, but got {}",
                         proto.name,
-                        argc,
+                        arity,
                         proto.args.len()
                     );
                 }
-
-                let mut arguments = Vec::with_capacity(argc);
-                proto
-                    .args
+                let what = format!("an argument of {}", proto.name);
+                let message = self.runtime_error(proto.start, &format!("{} is not a function", proto.name));
+                let function = self.number(Ast::IdentifierNode(proto.name), "a called function", named_values);
+                let arguments: Vec<LLVMValueRef> = proto.args.into_iter().map(|ast| self.number(ast, &what, named_values)).collect();
+                unsafe { self.wrapper.call_value(function, &arguments, &message) }
+            }
+            Ast::CallNode(proto) if !self.defines(&proto.name) && intrinsic_arity(&proto.name).is_some() => {
+                let argc = intrinsic_arity(&proto.name).unwrap();
+                abort_if_not!(
+                    argc == proto.args.len(),
+                    "Incorrect number of arguments passed to {}. Expected {}, but got {}",
+                    proto.name,
+                    argc,
+                    proto.args.len()
+                );
+                let what = format!("an argument of {}", proto.name);
+                let mut arguments: Vec<LLVMValueRef> = proto.args.into_iter().map(|ast| self.number(ast, &what, named_values)).collect();
+                unsafe { self.wrapper.call_intrinsic(&proto.name, &mut arguments) }
+            }
+            Ast::CallNode(proto) if !self.defines(&proto.name) && vector_builtin_arity(&proto.name).is_some() => {
+                let argc = vector_builtin_arity(&proto.name).unwrap();
+                abort_if_not!(
+                    argc == proto.args.len(),
+                    "Incorrect number of arguments passed to {}. Expected {}, but got {}",
+                    proto.name,
+                    argc,
+                    proto.args.len()
+                );
+                self.expect_vector(&proto.args[0], &format!("the first argument of {}", proto.name), named_values);
+                let not_vector = self.runtime_error(proto.start, &format!("{} takes a vector", proto.name));
+                let message = self.runtime_error(proto.start, "pop from an empty vector");
+                let out_of_memory = self.runtime_error(proto.start, "out of memory");
+                let what = format!("an argument of {}", proto.name);
+                let arguments: Vec<LLVMValueRef> = proto.args.into_iter().map(|ast| self.number(ast, &what, named_values)).collect();
+                unsafe {
+                    self.wrapper.check_vector(arguments[0], &not_vector);
+                    match proto.name.as_str() {
+                        "push" => self.wrapper.vector_push(arguments[0], arguments[1], &out_of_memory),
+                        "pop" => self.wrapper.vector_pop(arguments[0], &message),
+                        _ => self.wrapper.vector_len(arguments[0]),
+                    }
+                }
+            }
+            Ast::CallNode(proto) if !self.defines(&proto.name) && memory_builtin_arity(&proto.name).is_some() => {
+                let argc = memory_builtin_arity(&proto.name).unwrap();
+                abort_if_not!(
+                    argc == proto.args.len(),
+                    "Incorrect number of arguments passed to {}. Expected {}, but got {}",
+                    proto.name,
+                    argc,
+                    proto.args.len()
+                );
+                // What the compiler knows to be wrong is an error, the rest is checked when the program runs
+                let (misused, expected) = match (proto.name.as_str(), &proto.args[0]) {
+                    ("alloc", _) => (false, "cannot allocate that many numbers".to_string()),
+                    ("free", Ast::IdentifierNode(name)) => (
+                        !named_values.contains_key(name) && self.vtable.contains_key(name),
+                        "free takes memory from alloc, a vector or a closure".to_string(),
+                    ),
+                    ("free", ast) => (
+                        !matches!(ast, Ast::ClosureNode { .. }) && self.holds_vector(ast, named_values) == Some(false),
+                        "free takes memory from alloc, a vector or a closure".to_string(),
+                    ),
+                    (name, ast) => (self.holds_vector(ast, named_values).is_some(), format!("{name} takes an address from alloc or offset")),
+                };
+                abort_if_not!(!misused, "{}", expected);
+                let start = proto.start;
+                let message = self.runtime_error(start, &expected);
+                let what = format!("an argument of {}", proto.name);
+                let arguments: Vec<LLVMValueRef> = proto.args.into_iter().map(|ast| self.number(ast, &what, named_values)).collect();
+                unsafe {
+                    match proto.name.as_str() {
+                        "alloc" => self.wrapper.alloc(arguments[0], &message),
+                        "free" => {
+                            self.wrapper.free(arguments[0], &message);
+                            self.wrapper.gen_fp(0.0)
+                        }
+                        "offset" => {
+                            let too_far = self.runtime_error(start, "cannot move an address that far");
+                            self.wrapper.offset(arguments[0], arguments[1], &message, &too_far)
+                        }
+                        "load" => self.wrapper.load(arguments[0], &message),
+                        _ => {
+                            self.wrapper.store(arguments[0], arguments[1], &message);
+                            arguments[1]
+                        }
+                    }
+                }
+            }
+            Ast::MethodNode { receiver, method, args, .. } => {
+                let Some(&arity) = self.methods.get(&method) else {
+                    abort!("No method {method}. Consider declaring it in a trait")
+                };
+                abort_if_not!(
+                    arity == args.len() + 1,
+                    "Incorrect number of arguments passed to {}. Expected {}, but got {}",
+                    method,
+                    arity - 1,
+                    args.len()
+                );
+                // The type of the receiver is known, so is the implementation
+                let mut arguments: Vec<LLVMValueRef> =
+                    std::iter::once(*receiver).chain(args).map(|ast| self.deref_or_generate(ast, named_values)).collect();
+                let target = self.type_name(arguments[0]);
+                let name = format!("{target}::{method}");
+                let Some(&function) = self.vtable.get(&name) else {
+                    abort!("{target} does not implement {method}")
+                };
+                self.check_arguments(&name, function, &arguments);
+                unsafe { self.wrapper.call_function(function, arguments.len(), arguments.as_mut_ptr()) }
+            }
+            Ast::VectorNode { elements, .. } => {
+                let elements: Vec<LLVMValueRef> =
+                    elements.into_iter().map(|ast| self.number(ast, "a vector element", named_values)).collect();
+                unsafe { self.wrapper.create_vector(&elements) }
+            }
+            Ast::IndexNode { vector, index, start } => {
+                let element = self.vector_element(*vector, *index, start, named_values);
+                unsafe { self.wrapper.deref(element, "element") }
+            }
+            Ast::ClosureNode { params, body, start } => {
+                // Only the variables of the function are captured, globals are in scope of every function anyway
+                let mut names = Vec::new();
+                used_names(&body, &mut names);
+                let captured: Vec<String> = names
                     .into_iter()
-                    .for_each(|ast| arguments.push(self.deref_or_generate(ast, named_values)));
-
-                unsafe { self.wrapper.call_function(function, argc, arguments.as_mut_ptr()) }
+                    .filter(|name| !params.contains(name))
+                    .filter(|name| {
+                        named_values
+                            .get(name)
+                            .is_some_and(|var| self.globals.get(name).is_none_or(|global| global.value != var.value))
+                    })
+                    .collect();
+                if let Some(name) = captured.iter().find(|name| named_values[*name].reference.is_some()) {
+                    abort!("Closures cannot capture the reference {name}")
+                }
+                let values: Vec<LLVMValueRef> = captured
+                    .iter()
+                    .map(|name| self.deref_or_generate(Ast::IdentifierNode(name.clone()), named_values))
+                    .collect();
+                if let Some((name, value)) = captured.iter().zip(&values).find(|(_, value)| !self.is_number(**value)) {
+                    abort!("Closures cannot capture {}, which holds {}", name, self.type_name(*value))
+                }
+                let captures = captured.into_iter().map(|name| {
+                    let arity = named_values[&name].arity;
+                    (name, arity)
+                });
+                let function = unsafe { self.wrapper.declare_function(CLOSURE, params.len() + 1) };
+                self.closures.push(Closure {
+                    function,
+                    params,
+                    captures: captures.collect(),
+                    body: *body,
+                    start,
+                });
+                unsafe { self.wrapper.create_closure(function, &values) }
             }
-            Ast::BinaryNode { left, right, op } => match op {
-                BinaryOperatorKind::Addition => unsafe {
-                    let lhs = self.deref_or_generate(*left, named_values);
-                    let rhs = self.deref_or_generate(*right, named_values);
-                    self.wrapper.add(lhs, rhs)
-                },
-                BinaryOperatorKind::Multiplication => unsafe {
-                    let lhs = self.deref_or_generate(*left, named_values);
-                    let rhs = self.deref_or_generate(*right, named_values);
-                    self.wrapper.mul(lhs, rhs)
-                },
-                BinaryOperatorKind::Subtraction => unsafe {
-                    let lhs = self.deref_or_generate(*left, named_values);
-                    let rhs = self.deref_or_generate(*right, named_values);
-                    self.wrapper.sub(lhs, rhs)
-                },
-                BinaryOperatorKind::Division => unsafe {
-                    let lhs = self.deref_or_generate(*left, named_values);
-                    let rhs = self.deref_or_generate(*right, named_values);
-                    self.wrapper.div(lhs, rhs)
-                },
-                BinaryOperatorKind::Comparision(cmp) => unsafe {
-                    let lhs = self.deref_or_generate(*left, named_values);
-                    let rhs = self.deref_or_generate(*right, named_values);
-                    self.wrapper.cmp(lhs, rhs, cmp)
-                },
+            Ast::CallNode(proto) => {
+                if let Some(count) = self.tuples.get(&proto.name) {
+                    abort!("{} returns {} values. Consider destructuring them with `let`", proto.name, count)
+                }
+                let (function, mut arguments) = self.prepare_call(proto, named_values);
+                unsafe { self.wrapper.call_function(function, arguments.len(), arguments.as_mut_ptr()) }
+            }
+            Ast::BinaryNode { left, right, op, .. } => match op {
+                BinaryOperatorKind::Assigment if matches!(*left, Ast::IndexNode { .. } | Ast::DerefNode { .. }) => {
+                    let what = if matches!(*left, Ast::IndexNode { .. }) { "a vector element" } else { "a referenced value" };
+                    let target = self.assignee(*left, named_values);
+                    let rhs = self.number(*right, what, named_values);
+                    unsafe { self.wrapper.assign2var(rhs, target) };
+                    rhs
+                }
                 BinaryOperatorKind::Assigment => unsafe {
                     if let Ast::IdentifierNode(ref ident) = *left {
-                        if named_values.get(ident).unwrap().is_function_arg {
+                        let Some(var) = named_values.get(ident) else {
+                            abort!("No variable {ident}. Consider creating it")
+                        };
+                        if var.reference.is_some() {
+                            abort!("{ident} is a reference. Consider assigning to `*{ident}`");
+                        }
+                        if var.is_function_arg {
                             abort!("Cannot assign to const variable {ident}");
                         }
+                        if var.vector && self.holds_vector(&right, named_values) == Some(false) {
+                            abort!("{ident} holds vectors. Consider creating another variable");
+                        }
+                        if let (Some(arity), Some(assigned)) = (var.arity, self.function_arity(&right, named_values)) {
+                            abort_if_not!(
+                                arity == assigned,
+                                "{} holds functions taking {} arguments, but got one taking {}",
+                                ident,
+                                arity,
+                                assigned
+                            );
+                        }
                     }
+                    let name = match *left {
+                        Ast::IdentifierNode(ref ident) => ident.clone(),
+                        _ => String::new(),
+                    };
                     let lhs = self.generate_ir(*left, named_values);
-                    let rhs = self.generate_ir(*right, named_values);
+                    let rhs = self.deref_or_generate(*right, named_values);
+                    let (held, assigned) = (self.wrapper.stored_type(lhs), self.wrapper.type_of(rhs));
+                    abort_if_not!(
+                        held == assigned,
+                        "Cannot assign {} to {}, which holds {}",
+                        self.wrapper.type_name(assigned),
+                        name,
+                        self.wrapper.type_name(held)
+                    );
                     self.wrapper.assign2var(rhs, lhs);
                     rhs
                 },
+                op => {
+                    self.expect_not_vector(&left, named_values);
+                    self.expect_not_vector(&right, named_values);
+                    let lhs = self.deref_or_generate(*left, named_values);
+                    let rhs = self.deref_or_generate(*right, named_values);
+                    self.apply(op, lhs, rhs)
+                }
             },
-            Ast::RetNode(ret) => unsafe {
-                let ret = self.deref_or_generate(*ret, named_values);
+            Ast::CompoundAssignNode { assignee, value, op, .. } => {
+                self.expect_not_vector(&assignee, named_values);
+                self.expect_not_vector(&value, named_values);
+                let var = self.assignee(*assignee, named_values);
+                let rhs = self.deref_or_generate(*value, named_values);
+                unsafe {
+                    let lhs = self.wrapper.deref(var, "deref");
+                    let result = self.apply(op, lhs, rhs);
+                    self.wrapper.assign2var(result, var);
+                    result
+                }
+            }
+            Ast::RetNode { value, .. } if self.results > 1 => {
+                let values = self.tuple_values(*value, self.results, named_values);
+                self.run_deferred(self.deferred.concat());
+                unsafe { self.wrapper.build_return_tuple(&values) }
+            }
+            Ast::RetNode { value, .. } => unsafe {
+                abort_if_not!(!matches!(*value, Ast::TupleNode { .. }), "Only functions declared with `-> (types)` return tuples");
+                let ret = self.deref_or_generate(*value, named_values);
+                let (expected, returned) = (self.wrapper.current_return_type(), self.wrapper.type_of(ret));
+                abort_if_not!(
+                    expected == returned,
+                    "Expected to return {}, but got {}",
+                    self.wrapper.type_name(expected),
+                    self.wrapper.type_name(returned)
+                );
+                self.run_deferred(self.deferred.concat());
                 self.wrapper.build_return(ret)
             },
+            Ast::DeferNode { value, .. } => {
+                let Some(scope) = self.deferred.last_mut() else {
+                    abort!("Please report how you have bypassed the parser")
+                };
+                // Names in the expression mean the variables they do here, even if they are shadowed later
+                scope.push((*value, named_values.clone()));
+                std::ptr::null_mut()
+            }
+            Ast::DestructureNode { names, value, start } => {
+                let values = self.tuple_values(*value, names.len(), named_values);
+                for (name, value) in names.into_iter().zip(values) {
+                    if name != "_" {
+                        let var = unsafe { self.wrapper.create_var(value, &name, start) };
+                        named_values.insert(name, Variable::new(var, false));
+                    }
+                }
+                std::ptr::null_mut()
+            }
+            Ast::TupleNode { .. } => abort!("Tuples can only be returned or destructured with `let`"),
+            Ast::DerefNode { name, .. } => {
+                let var = self.reference(&name, named_values);
+                unsafe { self.wrapper.deref(var.value, &name) }
+            }
+            Ast::ReferenceNode { .. } => abort!("References can only be passed to functions taking them"),
             Ast::UnitNode(unit) => self.generate_ir(*unit, named_values),
-            Ast::IfNode { condition, body } => {
-                let condition = self.generate_ir(*condition, named_values);
+            Ast::IfNode { condition, body, else_body: Some(else_body), .. } => {
+                let condition = self.number(*condition, "a condition", named_values);
+                let (_, otherwise, merge) = unsafe { self.wrapper.create_branches(condition) };
+                let (then_value, then_end) = self.generate_branch(body, merge, otherwise, named_values);
+                let (else_value, else_end) = self.generate_branch(else_body, merge, merge, named_values);
+                match (then_value, else_value) {
+                    (Some(then_value), Some(else_value)) => self.merge(&mut [then_value, else_value], &mut [then_end, else_end], "blocks of the `if`"),
+                    _ => std::ptr::null_mut(),
+                }
+            }
+            Ast::IfNode { condition, body, .. } => {
+                let condition = self.number(*condition, "a condition", named_values);
                 let merge = unsafe { self.wrapper.create_condition(condition) };
-                let branch = body.iter().any(|ast| matches!(ast, Ast::RetNode(_))); // Ha-ha brrrrr
-                body.into_iter().for_each(|ast| {
-                    self.generate_ir(ast, named_values);
-                });
+                let branch = body.iter().any(|ast| matches!(ast, Ast::RetNode { .. })); // Ha-ha brrrrr
+                self.generate_block(body, branch, named_values);
                 unsafe {
                     self.wrapper.terminate_condition(merge, branch);
                 }
                 std::ptr::null_mut() // if is statement
             }
-            Ast::WhileNode { condition, body } => {
+            Ast::MatchNode { value, arms, start } => {
+                let value = self.deref_or_generate(*value, named_values);
+                let (blocks, merge) = self.dispatch(value, &arms, start);
+                let mut values = Vec::with_capacity(arms.len());
+                let mut ends = Vec::with_capacity(arms.len());
+                for (i, arm) in arms.into_iter().enumerate() {
+                    let bindings = match arm.pattern {
+                        Pattern::Variant { bindings, .. } => bindings,
+                        _ => Vec::new(),
+                    };
+                    // The fields are immutable like function parameters, and `_` binds nothing
+                    let fields = match bindings.len() {
+                        0 => Vec::new(),
+                        count => unsafe { self.wrapper.variant_fields(value, count) },
+                    };
+                    let shadowed: Vec<(String, Option<Variable>)> = bindings
+                        .into_iter()
+                        .zip(fields)
+                        .filter(|(binding, _)| binding != "_")
+                        .map(|(binding, field)| {
+                            let shadowed = named_values.insert(binding.clone(), Variable::new(field, true));
+                            (binding, shadowed)
+                        })
+                        .collect();
+                    let next = blocks.get(i + 1).copied().unwrap_or(merge);
+                    let (value, end) = self.generate_branch(arm.body, merge, next, named_values);
+                    for (binding, shadowed) in shadowed.into_iter().rev() {
+                        match shadowed {
+                            Some(shadowed) => named_values.insert(binding, shadowed),
+                            None => named_values.remove(&binding),
+                        };
+                    }
+                    values.push(value);
+                    ends.push(end);
+                }
+                match values.into_iter().collect::<Option<Vec<LLVMValueRef>>>() {
+                    Some(mut values) => self.merge(&mut values, &mut ends, "arms of the `match`"),
+                    None => std::ptr::null_mut(),
+                }
+            }
+            Ast::WhileNode { condition, body, .. } => {
                 let (loop_body, merge) = unsafe { self.wrapper.create_loop() };
                 // I don't care at this point, Ctrl+C/V goes brrrrr
-                let branch = body.iter().any(|ast| matches!(ast, Ast::RetNode(_))); // Ha-ha brrrrr
-                body.into_iter().for_each(|ast| {
-                    self.generate_ir(ast, named_values);
-                });
-                let condition = self.generate_ir(*condition, named_values);
+                let branch = body.iter().any(|ast| matches!(ast, Ast::RetNode { .. })); // Ha-ha brrrrr
+                self.generate_block(body, branch, named_values);
+                let condition = self.number(*condition, "a condition", named_values);
                 unsafe {
                     self.wrapper.terminate_loop(condition, loop_body, merge, branch);
                 }
                 std::ptr::null_mut()
             }
+            Ast::ForNode { variable, from, to, inclusive, step, body, start } => {
+                let from = self.number(*from, "a bound of the loop", named_values);
+                let to = self.number(*to, "a bound of the loop", named_values);
+                // The loop counts down when the step is negative. A constant step picks the direction when compiling
+                let constant = step.as_deref().map_or(Some(1.0), |step| self.evaluate(step));
+                abort_if_not!(constant != Some(0.0), "The step of the loop over {} is 0, the loop would never end", variable);
+                let step = match step {
+                    Some(step) => self.number(*step, "the step of the loop", named_values),
+                    None => unsafe { self.wrapper.gen_fp(1.0) },
+                };
+                if constant.is_none() {
+                    let message = self.runtime_error(start, "the step of the loop is 0");
+                    unsafe { self.wrapper.check_nonzero(step, &message) }
+                }
+                let (up, down) = if inclusive {
+                    (ComparisionOpKind::LessOrEq, ComparisionOpKind::BiggerOrEq)
+                } else {
+                    (ComparisionOpKind::Less, ComparisionOpKind::Bigger)
+                };
+                let within = |cg: &Self, counter: LLVMValueRef| unsafe {
+                    match constant {
+                        Some(step) if step > 0.0 => cg.wrapper.cmp(counter, to, up),
+                        Some(_) => cg.wrapper.cmp(counter, to, down),
+                        None => cg.wrapper.select_by_sign(step, cg.wrapper.cmp(counter, to, up), cg.wrapper.cmp(counter, to, down)),
+                    }
+                };
+                let counter = unsafe { self.wrapper.create_var(from, &variable, start) };
+                let (loop_body, merge) = unsafe { self.wrapper.create_guarded_loop(within(self, from)) };
+
+                // Inside the body the variable is a plain value, immutable like function parameters
+                let current = unsafe { self.wrapper.deref(counter, &variable) };
+                let shadowed = named_values.insert(variable.clone(), Variable::new(current, true));
+                let branch = body.iter().any(|ast| matches!(ast, Ast::RetNode { .. }));
+                self.generate_block(body, branch, named_values);
+                match shadowed {
+                    Some(shadowed) => named_values.insert(variable, shadowed),
+                    None => named_values.remove(&variable),
+                };
+
+                unsafe {
+                    let mut condition = std::ptr::null_mut();
+                    if !branch {
+                        let next = self.wrapper.add(self.wrapper.deref(counter, "deref"), step);
+                        self.wrapper.assign2var(next, counter);
+                        condition = within(self, next);
+                    }
+                    self.wrapper.terminate_loop(condition, loop_body, merge, branch);
+                }
+                std::ptr::null_mut()
+            }
+            Ast::ForEachNode { variable, vector, body, start } => {
+                self.expect_vector(&vector, "what `for` iterates over", named_values);
+                let not_vector = self.runtime_error(start, "only vectors can be iterated over");
+                let vector = self.number(*vector, "a vector", named_values);
+                unsafe { self.wrapper.check_vector(vector, &not_vector) };
+                let message = self.runtime_error(start, "the vector was shrunk while iterating over it");
+                let counter = unsafe { self.wrapper.create_var(self.wrapper.gen_fp(0.0), "index", start) };
+                let (loop_body, merge) = unsafe {
+                    let len = self.wrapper.vector_len(vector);
+                    self.wrapper.create_guarded_loop(self.wrapper.cmp(self.wrapper.gen_fp(0.0), len, ComparisionOpKind::Less))
+                };
+
+                let element = unsafe {
+                    let index = self.wrapper.deref(counter, "index");
+                    self.wrapper.deref(self.wrapper.vector_element(vector, index, &message), &variable)
+                };
+                let shadowed = named_values.insert(variable.clone(), Variable::new(element, true));
+                let branch = body.iter().any(|ast| matches!(ast, Ast::RetNode { .. }));
+                self.generate_block(body, branch, named_values);
+                match shadowed {
+                    Some(shadowed) => named_values.insert(variable, shadowed),
+                    None => named_values.remove(&variable),
+                };
+
+                unsafe {
+                    let mut condition = std::ptr::null_mut();
+                    if !branch {
+                        let next = self.wrapper.add(self.wrapper.deref(counter, "deref"), self.wrapper.gen_fp(1.0));
+                        self.wrapper.assign2var(next, counter);
+                        condition = self.wrapper.cmp(next, self.wrapper.vector_len(vector), ComparisionOpKind::Less);
+                    }
+                    self.wrapper.terminate_loop(condition, loop_body, merge, branch);
+                }
+                std::ptr::null_mut()
+            }
             _ => abort!("Your code uses a not implemented yet feature. Thus aborting. Sorry"),
         }
     }
-    fn set_arguments(&mut self, function: LLVMValueRef, args: Vec<Ast>, placeholder: &mut HashMap<String, Variable>) {
-        for (i, arg) in args.into_iter().enumerate() {
+    fn set_arguments(
+        &mut self,
+        function: LLVMValueRef,
+        args: Vec<Ast>,
+        references: &[Option<bool>],
+        arities: &[Option<usize>],
+        placeholder: &mut HashMap<String, Variable>,
+    ) {
+        for (i, ((arg, reference), arity)) in args.into_iter().zip(references).zip(arities).enumerate() {
             if let Ast::IdentifierNode(arg) = arg {
                 let param = unsafe { self.wrapper.set_param2function(function, &arg, i) };
-                placeholder.insert(arg, Variable::new(param, true));
+                placeholder.insert(arg, Variable::new(param, true).with_reference(*reference).with_arity(*arity));
+            }
+        }
+    }
+    /// Declares the function `proto` is the prototype of, remembering what it returns and which parameters take references
+    fn declare_function(&mut self, proto: &Proto) -> LLVMValueRef {
+        abort_if_not!(!self.defines(&proto.name), "Function {} already exists!", proto.name);
+        let params: Vec<LLVMTypeRef> =
+            (0..proto.args.len()).map(|i| self.resolve_type(proto.types.get(i).and_then(Option::as_ref), &proto.generics)).collect();
+        let returns = self.resolve_type(proto.returns.as_ref(), &proto.generics);
+        let symbol = format!("{SYMBOL_PREFIX}{}", proto.name);
+        let function = unsafe { self.wrapper.declare_function_of_type(&symbol, &params, returns) };
+        self.remember_shape(&proto.name, proto);
+        self.vtable.insert(proto.name.clone(), function);
+        function
+    }
+    /// Remembers whether the function `name` returns a tuple and which of its parameters take references
+    fn remember_shape(&mut self, name: &str, proto: &Proto) {
+        let results = proto.results();
+        let references = proto.references();
+        if results > 1 {
+            self.tuples.insert(name.into(), results);
+        }
+        if references.iter().any(Option::is_some) {
+            self.references.insert(name.into(), references);
+        }
+        let arities = proto.arities();
+        if arities.iter().any(Option::is_some) {
+            self.arities.insert(name.into(), arities);
+        }
+    }
+    /// Whether a function or a generic function is called `name`
+    fn defines(&self, name: &str) -> bool {
+        self.vtable.contains_key(name) || self.generics.contains_key(name)
+    }
+    /// Generates the arguments of a call, and finds the function it goes to
+    fn prepare_call(&mut self, proto: Proto, named_values: &mut HashMap<String, Variable>) -> (LLVMValueRef, Vec<LLVMValueRef>) {
+        let argc = match (self.vtable.get(&proto.name), self.generics.get(&proto.name)) {
+            (Some(function), _) => unsafe { self.wrapper.count_args(*function) },
+            (None, Some(generic)) => generic.proto.args.len(),
+            (None, None) => abort!("No function {}. Define it before calling", proto.name),
+        };
+        abort_if_not!(
+            argc == proto.args.len(),
+            "Incorrect number of arguments passed to {}. Expected {}, but got {}",
+            proto.name,
+            argc,
+            proto.args.len()
+        );
+        if let Some(arities) = self.arities.get(&proto.name) {
+            for (i, (arity, ast)) in arities.iter().zip(&proto.args).enumerate() {
+                if let (Some(arity), Some(passed)) = (arity, self.function_arity(ast, named_values)) {
+                    abort_if_not!(
+                        *arity == passed,
+                        "Parameter {} of {} takes functions taking {} arguments, but got one taking {}",
+                        i + 1,
+                        proto.name,
+                        arity,
+                        passed
+                    );
+                }
+            }
+        }
+        let arguments = self.arguments(&proto.name, proto.args, named_values);
+        let function = self.instantiate(&proto.name, &arguments);
+        self.check_arguments(&proto.name, function, &arguments);
+        (function, arguments)
+    }
+    /// The function `name` called with the arguments. A generic function is declared for the types of the
+    /// arguments its type parameters stand for, and generated once the modules are. Type parameters no parameter is
+    /// annotated with stand for `f64`
+    fn instantiate(&mut self, name: &str, arguments: &[LLVMValueRef]) -> LLVMValueRef {
+        let Some(generic) = self.generics.get(name) else {
+            return self.vtable[name];
+        };
+        let generics = &generic.proto.generics;
+        let mut types: Vec<Option<String>> = vec![None; generics.len()];
+        for (ty, argument) in generic.proto.types.iter().zip(arguments) {
+            let Some(i) = generics.iter().position(|generic| matches!(ty, Some(Type::Name(name)) if name == generic)) else {
+                continue;
+            };
+            let found = self.type_name(*argument);
+            if let Some(bound) = &types[i] {
+                abort_if_not!(*bound == found, "{} of {} stands for both {} and {}", generics[i], name, bound, found);
             }
+            types[i] = Some(found);
         }
+        let types: Vec<String> = types.into_iter().map(|ty| ty.unwrap_or_else(|| "f64".into())).collect();
+        let instance = format!("{name}<{}>", types.join(", "));
+        if let Some(&function) = self.vtable.get(&instance) {
+            return function;
+        }
+        let specialize = |ty: &Option<Type>| ty.as_ref().map(|ty| specialize(ty, generics, &types));
+        let proto = Proto {
+            name: instance,
+            args: generic.proto.args.clone(),
+            generics: Vec::new(),
+            types: generic.proto.types.iter().map(specialize).collect(),
+            returns: specialize(&generic.proto.returns),
+            start: generic.proto.start,
+        };
+        let (body, module) = (generic.body.clone(), generic.module);
+        let function = self.declare_function(&proto);
+        self.instances.push(Definition { proto, body, module });
+        function
     }
+    /// Generates the body of a declared function
     fn create_function(&mut self, proto: Proto, body: Vec<Ast>) {
-        let function = unsafe { self.wrapper.create_function(&proto.name, proto.args.len()) };
+        let function = self.vtable[&proto.name];
+        unsafe { self.wrapper.define_function(function, &proto.name, proto.args.len(), proto.start) };
+        self.results = proto.results();
+
+        let mut named_values = self.globals.clone();
+        let (references, arities) = (proto.references(), proto.arities());
+        self.set_arguments(function, proto.args, &references, &arities, &mut named_values);
 
-        let mut named_values = HashMap::<String, Variable>::new();
-        self.set_arguments(function, proto.args, &mut named_values);
+        // Functions end with `ret`, which generates what is deferred
+        self.generate_block(body, true, &mut named_values);
 
-        self.vtable.insert(proto.name, function);
+        unsafe {
+            self.wrapper.finalize_function(function);
+        }
+        // Asserts inside closures do not belong to the test, and closures return one value
+        self.results = 1;
+        let in_test = std::mem::replace(&mut self.in_test, false);
+        while let Some(closure) = self.closures.pop() {
+            self.create_closure(closure);
+        }
+        self.in_test = in_test;
+    }
+    /// Generates the function of a closure, which takes the closure itself before the parameters
+    fn create_closure(&mut self, closure: Closure) {
+        let Closure { function, params, captures, body, start } = closure;
+        unsafe { self.wrapper.define_function(function, CLOSURE, params.len() + 1, start) };
 
-        for instruction in body {
-            self.generate_ir(instruction, &mut named_values);
+        let mut named_values = self.globals.clone();
+        let environment = unsafe { self.wrapper.set_param2function(function, "closure", 0) };
+        let values = unsafe { self.wrapper.load_captures(environment, captures.len()) };
+        for ((name, arity), value) in captures.into_iter().zip(values) {
+            named_values.insert(name, Variable::new(value, true).with_arity(arity));
+        }
+        for (i, param) in params.into_iter().enumerate() {
+            let value = unsafe { self.wrapper.set_param2function(function, &param, i + 1) };
+            named_values.insert(param, Variable::new(value, true));
         }
 
+        let value = self.number(body, "the result of a closure", &mut named_values);
         unsafe {
-            self.wrapper.check_function(function);
+            self.wrapper.build_return(value);
+            self.wrapper.finalize_function(function);
+        }
+    }
+    /// Jumps to the block of the first arm matching `value`, the program fails if there is none. Returns the blocks of
+    /// the arms, positioned in the first one, and the block they meet in. Variants and whole numbers are switched over,
+    /// other numbers and ranges are compared with one by one
+    fn dispatch(&self, value: LLVMValueRef, arms: &[Arm], start: usize) -> (Vec<LLVMBasicBlockRef>, LLVMBasicBlockRef) {
+        let message = self.runtime_error(start, "no arm matches the value");
+        if arms.iter().any(|arm| matches!(arm.pattern, Pattern::Variant { .. })) {
+            let cases: Vec<Option<i64>> = arms
+                .iter()
+                .map(|arm| match &arm.pattern {
+                    Pattern::Variant { name, .. } => match self.variants.get(name) {
+                        Some((tag, _, ty)) => {
+                            abort_if_not!(*ty == unsafe { self.wrapper.type_of(value) }, "Cannot match {} against {}", self.type_name(value), name);
+                            Some(*tag as i64)
+                        }
+                        None => abort!("No variant {name}. Define it in an enum"),
+                    },
+                    _ => None,
+                })
+                .collect();
+            return unsafe { self.wrapper.create_switch(self.wrapper.variant_tag(value), &cases, false, &message) };
+        }
+        // Any value goes to a lone `_`, other values are matched against numbers
+        let value = if arms.iter().all(|arm| arm.pattern == Pattern::Wildcard) {
+            unsafe { self.wrapper.gen_fp(0.0) }
+        } else {
+            self.expect_number(value, "a value matched against numbers")
+        };
+        let whole = |n: f64| n.fract() == 0.0 && n.abs() < 2f64.powi(63);
+        if arms.iter().all(|arm| match arm.pattern {
+            Pattern::Number(n) => whole(n),
+            Pattern::Wildcard => true,
+            _ => false,
+        }) {
+            #[allow(clippy::cast_possible_truncation)]
+            let cases: Vec<Option<i64>> = arms
+                .iter()
+                .map(|arm| match arm.pattern {
+                    Pattern::Number(n) => Some(n as i64),
+                    _ => None,
+                })
+                .collect();
+            return unsafe { self.wrapper.create_switch(value, &cases, true, &message) };
+        }
+        let ranges: Vec<Option<(f64, f64, bool)>> = arms
+            .iter()
+            .map(|arm| match arm.pattern {
+                Pattern::Number(n) => Some((n, n, true)),
+                Pattern::Range { from, to, inclusive } => Some((from, to, inclusive)),
+                _ => None,
+            })
+            .collect();
+        unsafe { self.wrapper.create_chain(value, &ranges, &message) }
+    }
+    /// Returns the address of the element, checking that it exists when the program runs
+    fn vector_element(&mut self, vector: Ast, index: Ast, start: usize, named_values: &mut HashMap<String, Variable>) -> LLVMValueRef {
+        self.expect_vector(&vector, "what is indexed", named_values);
+        let not_vector = self.runtime_error(start, "only vectors can be indexed");
+        let message = self.runtime_error(start, "index out of bounds");
+        let vector = self.number(vector, "a vector", named_values);
+        let index = self.number(index, "an index", named_values);
+        unsafe {
+            self.wrapper.check_vector(vector, &not_vector);
+            self.wrapper.vector_element(vector, index, &message)
+        }
+    }
+    /// The reference `name`, which `*name` reads or writes through
+    fn reference(&self, name: &str, named_values: &HashMap<String, Variable>) -> Variable {
+        match named_values.get(name) {
+            Some(var) if var.reference.is_some() => *var,
+            Some(_) => abort!("{name} is not a reference"),
+            None => abort!("No variable {name}. Consider creating it"),
+        }
+    }
+    /// Pointer to what a variable, `*reference` or `vector[index]` assignee stores to
+    fn assignee(&mut self, ast: Ast, named_values: &mut HashMap<String, Variable>) -> LLVMValueRef {
+        match ast {
+            Ast::IdentifierNode(name) => {
+                let Some(var) = named_values.get(&name) else {
+                    abort!("No variable {name}. Define it before assigning")
+                };
+                abort_if_not!(var.reference.is_none(), "{} is a reference. Consider assigning to `*{}`", name, name);
+                abort_if_not!(!var.is_function_arg, "Cannot assign to const variable {name}");
+                var.value
+            }
+            Ast::DerefNode { name, .. } => {
+                let var = self.reference(&name, named_values);
+                abort_if_not!(var.reference == Some(true), "Cannot assign through {}, which is not a `&mut` reference", name);
+                var.value
+            }
+            Ast::IndexNode { vector, index, start } => self.vector_element(*vector, *index, start, named_values),
+            _ => abort!("Please report how you have bypassed the parser"),
+        }
+    }
+    /// Generates the arguments of a call to the function `name`, passing pointers to the parameters taking references
+    fn arguments(&mut self, name: &str, args: Vec<Ast>, named_values: &mut HashMap<String, Variable>) -> Vec<LLVMValueRef> {
+        let Some(references) = self.references.get(name).cloned() else {
+            return args.into_iter().map(|ast| self.deref_or_generate(ast, named_values)).collect();
+        };
+        let mut arguments = Vec::with_capacity(args.len());
+        for (i, (ast, reference)) in args.into_iter().zip(references).enumerate() {
+            let Some(mutable) = reference else {
+                arguments.push(self.deref_or_generate(ast, named_values));
+                continue;
+            };
+            let sigil = if mutable { "&mut" } else { "&" };
+            let Ast::ReferenceNode { name: variable, mutable: borrowed, start } = ast else {
+                abort!("{} takes `{}` as parameter {}. Consider passing `{} name`", name, sigil, i + 1, sigil)
+            };
+            abort_if_not!(borrowed || !mutable, "{} takes `&mut` as parameter {}, but got `&{}`", name, i + 1, variable);
+            let Some(var) = named_values.get(&variable).copied() else {
+                abort!("No variable {variable}. Consider creating it")
+            };
+            let pointer = match var.reference {
+                // References are passed on
+                Some(writable) => {
+                    abort_if_not!(writable || !borrowed, "Cannot borrow {} as `&mut`, it is a `&` reference", variable);
+                    var.value
+                }
+                None if !var.is_function_arg => var.value,
+                None => {
+                    abort_if_not!(!borrowed, "Cannot borrow const variable {} as `&mut`", variable);
+                    // Immutable values are not stored anywhere, so they are copied for the callee to point to
+                    unsafe { self.wrapper.create_var(var.value, &variable, start) }
+                }
+            };
+            arguments.push(pointer);
+        }
+        arguments
+    }
+    /// Generates the `count` values of a tuple, or of a call to a function returning one
+    fn tuple_values(&mut self, ast: Ast, count: usize, named_values: &mut HashMap<String, Variable>) -> Vec<LLVMValueRef> {
+        match ast {
+            Ast::TupleNode { elements, .. } => {
+                abort_if_not!(elements.len() == count, "Expected a tuple of {} values, but got {}", count, elements.len());
+                elements.into_iter().map(|ast| self.number(ast, "a tuple element", named_values)).collect()
+            }
+            Ast::CallNode(proto) if self.tuples.contains_key(&proto.name) && !named_values.contains_key(&proto.name) => {
+                let results = self.tuples[&proto.name];
+                abort_if_not!(results == count, "Expected a tuple of {} values, but {} returns {}", count, proto.name, results);
+                let (function, mut arguments) = self.prepare_call(proto, named_values);
+                unsafe {
+                    let tuple = self.wrapper.call_function(function, arguments.len(), arguments.as_mut_ptr());
+                    self.wrapper.tuple_elements(tuple, count)
+                }
+            }
+            _ => abort!("Expected a tuple of {} values", count),
+        }
+    }
+    /// Declares the functions of `impl name for target`, named `target::method`. They take a number or a value of the
+    /// enum as `self`
+    fn declare_impl(&mut self, name: &str, target: &str, functions: &[Ast]) {
+        let Some(required) = self.traits.get(name).cloned() else {
+            abort!("No trait {name}. Consider defining it")
+        };
+        abort_if_not!(target == "f64" || self.enums.contains_key(target), "No type {}. Expected f64 or an enum", target);
+        for (method, _) in &required {
+            abort_if_not!(
+                functions.iter().any(|f| matches!(f, Ast::FunctionNode { proto, .. } if proto.name == *method)),
+                "{} does not implement {} of {}",
+                target,
+                method,
+                name
+            );
+        }
+        for function in functions {
+            let Ast::FunctionNode { proto, .. } = function else {
+                abort!("Please report how you have bypassed the parser")
+            };
+            let Some(&(_, arity)) = required.iter().find(|(method, _)| *method == proto.name) else {
+                abort!("{} is not a method of {}", proto.name, name)
+            };
+            abort_if_not!(
+                arity == proto.args.len(),
+                "Method {} of {} takes {} parameters, but got {}",
+                proto.name,
+                name,
+                arity,
+                proto.args.len()
+            );
+            let method = &proto.name;
+            let mut proto = proto.clone();
+            proto.name = format!("{target}::{method}");
+            abort_if_not!(!self.vtable.contains_key(&proto.name), "{} already implements {}", target, method);
+            abort_if_not!(!self.variants.contains_key(&proto.name), "Function {} already exists!", proto.name);
+            proto.types[0] = Some(Type::Name(target.into()));
+            self.declare_function(&proto);
+        }
+    }
+    /// The message a failed runtime check prints, pointing at `start`
+    fn runtime_error(&self, start: usize, message: &str) -> String {
+        let line = self.lines.partition_point(|l| *l <= start);
+        format!("error in {} on line {}: {}", self.file, line, message)
+    }
+    /// Generates one of the blocks of an `if`-`else`, then continues in `next`.
+    /// Returns the value the block ends with, and the basic block it ends in. The variables the block defines are
+    /// forgotten after it
+    fn generate_branch(
+        &mut self,
+        body: Vec<Ast>,
+        merge: LLVMBasicBlockRef,
+        next: LLVMBasicBlockRef,
+        named_values: &mut HashMap<String, Variable>,
+    ) -> (Option<LLVMValueRef>, LLVMBasicBlockRef) {
+        let ret = body.iter().any(|ast| matches!(ast, Ast::RetNode { .. }));
+        let mut value = None;
+        let len = body.len();
+        let outer = named_values.clone();
+        self.deferred.push(Vec::new());
+        for (i, ast) in body.into_iter().enumerate() {
+            if i + 1 == len && ast.produces_value() {
+                value = Some(self.deref_or_generate(ast, named_values));
+            } else {
+                self.generate_ir(ast, named_values);
+            }
+        }
+        self.leave_scope(ret);
+        *named_values = outer;
+        let end = unsafe { self.wrapper.terminate_branch(merge, ret, next) };
+        (value, end)
+    }
+    /// Generates the statements of a block, and then what is deferred in it unless the block returns. The variables
+    /// the block defines are forgotten after it
+    fn generate_block(&mut self, body: Vec<Ast>, returns: bool, named_values: &mut HashMap<String, Variable>) {
+        let outer = named_values.clone();
+        self.deferred.push(Vec::new());
+        body.into_iter().for_each(|ast| {
+            self.generate_ir(ast, named_values);
+        });
+        self.leave_scope(returns);
+        *named_values = outer;
+    }
+    /// Forgets what the innermost block has deferred, after generating it if the block is left without returning.
+    /// `ret` has generated it otherwise
+    fn leave_scope(&mut self, returns: bool) {
+        let deferred = self.deferred.pop().unwrap_or_default();
+        if !returns {
+            self.run_deferred(deferred);
+        }
+    }
+    /// Generates the deferred expressions, the last deferred first. Blocks inside them see nothing deferred
+    fn run_deferred(&mut self, deferred: Vec<(Ast, HashMap<String, Variable>)>) {
+        let scopes = std::mem::take(&mut self.deferred);
+        for (ast, mut named_values) in deferred.into_iter().rev() {
+            self.generate_ir(ast, &mut named_values);
+        }
+        self.deferred = scopes;
+    }
+    /// Number of parameters of the function the expression refers to, if it is known at compile time
+    fn function_arity(&self, ast: &Ast, named_values: &HashMap<String, Variable>) -> Option<usize> {
+        let ident = match ast {
+            Ast::IdentifierNode(ident) => ident,
+            Ast::ClosureNode { params, .. } => return Some(params.len()),
+            _ => return None,
+        };
+        match named_values.get(ident) {
+            Some(var) => var.arity,
+            None => self.vtable.get(ident).map(|f| unsafe { self.wrapper.count_args(*f) }),
+        }
+    }
+    /// Whether the expression gives a vector or something else, if it is known at compile time
+    fn holds_vector(&self, ast: &Ast, named_values: &HashMap<String, Variable>) -> Option<bool> {
+        match ast {
+            Ast::VectorNode { .. } => Some(true),
+            Ast::ValueNode(_) | Ast::ClosureNode { .. } => Some(false),
+            Ast::BinaryNode { op, .. } if *op != BinaryOperatorKind::Assigment => Some(false),
+            Ast::UnitNode(unit) => self.holds_vector(unit, named_values),
+            Ast::IdentifierNode(ident) => match named_values.get(ident) {
+                Some(var) if var.vector => Some(true),
+                Some(var) => var.arity.map(|_| false),
+                None => self.vtable.contains_key(ident).then_some(false),
+            },
+            _ => None,
+        }
+    }
+    /// Stops with an error if the expression is known not to give a vector, `what` tells what it is used as
+    fn expect_vector(&self, ast: &Ast, what: &str, named_values: &HashMap<String, Variable>) {
+        abort_if_not!(self.holds_vector(ast, named_values) != Some(false), "Expected a vector as {}", what);
+    }
+    /// Stops with an error if the operand is known to be a vector
+    fn expect_not_vector(&self, ast: &Ast, named_values: &HashMap<String, Variable>) {
+        abort_if_not!(self.holds_vector(ast, named_values) != Some(true), "Expected a number as an operand, but got a vector");
+    }
+    /// Computes a constant expression, which may only use numbers and `const`s
+    fn evaluate(&self, ast: &Ast) -> Option<f64> {
+        match ast {
+            Ast::ValueNode(LiteralKind::Num(n)) => Some(*n),
+            Ast::IdentifierNode(ident) => self.constants.get(ident).copied(),
+            Ast::UnitNode(unit) => self.evaluate(unit),
+            Ast::BinaryNode { left, right, op, .. } => {
+                let (lhs, rhs) = (self.evaluate(left)?, self.evaluate(right)?);
+                // Comparisons give the same -1 for true as the generated code does
+                let truth = |b: bool| if b { -1.0 } else { 0.0 };
+                Some(match op {
+                    BinaryOperatorKind::Addition => lhs + rhs,
+                    BinaryOperatorKind::Subtraction => lhs - rhs,
+                    BinaryOperatorKind::Multiplication => lhs * rhs,
+                    BinaryOperatorKind::Division => lhs / rhs,
+                    BinaryOperatorKind::Remainder => lhs % rhs,
+                    BinaryOperatorKind::Power => lhs.powf(rhs),
+                    BinaryOperatorKind::FloorDivision => (lhs / rhs).floor(),
+                    BinaryOperatorKind::Comparision(cmp) => truth(match cmp {
+                        ComparisionOpKind::Equals => lhs == rhs,
+                        ComparisionOpKind::NeEq => lhs != rhs,
+                        ComparisionOpKind::Bigger => lhs > rhs,
+                        ComparisionOpKind::BiggerOrEq => lhs >= rhs,
+                        ComparisionOpKind::Less => lhs < rhs,
+                        ComparisionOpKind::LessOrEq => lhs <= rhs,
+                    }),
+                    BinaryOperatorKind::Assigment => return None,
+                })
+            }
+            _ => None,
+        }
+    }
+    /// The type of the values a parameter or result annotated with `ty` holds, numbers when it is not annotated
+    fn resolve_type(&self, ty: Option<&Type>, generics: &[String]) -> LLVMTypeRef {
+        let number = unsafe { self.wrapper.number_type() };
+        match ty {
+            None => number,
+            Some(Type::Name(name)) if name == "f64" || generics.contains(name) => number,
+            Some(Type::Name(name)) => match self.enums.get(name) {
+                Some((_, ty)) => *ty,
+                None => abort!("Unknown type {name}. Expected f64, a type parameter or an enum"),
+            },
+            Some(Type::Tuple(types)) => {
+                abort_if_not!(types.iter().all(|ty| self.resolve_type(Some(ty), generics) == number), "Tuples can only hold numbers");
+                unsafe { self.wrapper.tuple_type(types.len()) }
+            }
+            Some(Type::Reference { ty, .. }) => {
+                abort_if_not!(self.resolve_type(Some(ty), generics) == number, "References can only point to numbers");
+                unsafe { self.wrapper.reference_type() }
+            }
+            Some(Type::Function(params)) => {
+                abort_if_not!(
+                    params.iter().all(|ty| self.resolve_type(Some(ty), generics) == number),
+                    "Functions held by values can only take numbers"
+                );
+                number
+            }
+        }
+    }
+    /// Whether the function only takes and returns numbers, the way functions stored in variables are called
+    fn takes_numbers(&self, function: LLVMValueRef) -> bool {
+        unsafe {
+            let number = self.wrapper.number_type();
+            self.wrapper.return_type(function) == number && self.wrapper.param_types(function).iter().all(|ty| *ty == number)
+        }
+    }
+    /// Checks that the arguments of a call to the function `name` have the types of its parameters
+    fn check_arguments(&self, name: &str, function: LLVMValueRef, arguments: &[LLVMValueRef]) {
+        let params = unsafe { self.wrapper.param_types(function) };
+        for (i, (param, argument)) in params.into_iter().zip(arguments).enumerate() {
+            let ty = unsafe { self.wrapper.type_of(*argument) };
+            abort_if_not!(
+                param == ty,
+                "Expected {} as argument {} of {}, but got {}",
+                unsafe { self.wrapper.type_name(param) },
+                i + 1,
+                name,
+                self.type_name(*argument)
+            );
+        }
+    }
+    fn is_number(&self, value: LLVMValueRef) -> bool {
+        unsafe { self.wrapper.type_of(value) == self.wrapper.number_type() }
+    }
+    /// `f64` or the name of the enum the value belongs to
+    fn type_name(&self, value: LLVMValueRef) -> String {
+        unsafe { self.wrapper.type_name(self.wrapper.type_of(value)) }
+    }
+    /// Stops with an error unless the value is a number, `what` tells what the value is used as
+    fn expect_number(&self, value: LLVMValueRef, what: &str) -> LLVMValueRef {
+        abort_if_not!(self.is_number(value), "Expected a number as {}, but got {}", what, self.type_name(value));
+        value
+    }
+    /// Generates an expression that has to give a number
+    fn number(&mut self, ast: Ast, what: &str, named_values: &mut HashMap<String, Variable>) -> LLVMValueRef {
+        let value = self.deref_or_generate(ast, named_values);
+        self.expect_number(value, what)
+    }
+    /// Joins the values the blocks of an `if` or the arms of a `match` end with, which have to be of the same type
+    fn merge(&self, values: &mut [LLVMValueRef], ends: &mut [LLVMBasicBlockRef], what: &str) -> LLVMValueRef {
+        if let Some(other) = values.iter().find(|value| unsafe { self.wrapper.type_of(**value) != self.wrapper.type_of(values[0]) }) {
+            abort!("The {} give {} and {}", what, self.type_name(values[0]), self.type_name(*other))
+        }
+        unsafe { self.wrapper.build_phi(values, ends) }
+    }
+    /// Builds an arithmetic or comparison operator
+    fn apply(&self, op: BinaryOperatorKind, lhs: LLVMValueRef, rhs: LLVMValueRef) -> LLVMValueRef {
+        self.expect_number(lhs, "an operand");
+        self.expect_number(rhs, "an operand");
+        unsafe {
+            match op {
+                BinaryOperatorKind::Addition => self.wrapper.add(lhs, rhs),
+                BinaryOperatorKind::Multiplication => self.wrapper.mul(lhs, rhs),
+                BinaryOperatorKind::Subtraction => self.wrapper.sub(lhs, rhs),
+                BinaryOperatorKind::Division => self.wrapper.div(lhs, rhs),
+                BinaryOperatorKind::Remainder => self.wrapper.rem(lhs, rhs),
+                BinaryOperatorKind::Power => self.wrapper.call_intrinsic("pow", &mut [lhs, rhs]),
+                BinaryOperatorKind::FloorDivision => {
+                    let quotient = self.wrapper.div(lhs, rhs);
+                    self.wrapper.call_intrinsic("floor", &mut [quotient])
+                }
+                BinaryOperatorKind::Comparision(cmp) => self.wrapper.cmp(lhs, rhs, cmp),
+                BinaryOperatorKind::Assigment => unreachable!("assignments store to variables"),
+            }
         }
     }
     fn deref_or_generate(&mut self, ast: Ast, named_values: &mut HashMap<String, Variable>) -> LLVMValueRef {
@@ -188,20 +1356,122 @@ impl Cg {
         }
         self.generate_ir(ast, named_values)
     }
-    pub fn exec(mut self) -> i32 {
+    /// Whether a function is declared as `name`, including instances of generic functions
+    #[cfg(test)]
+    pub(crate) fn declares(&self, name: &str) -> bool {
+        self.vtable.contains_key(name)
+    }
+    fn main(&self) -> LLVMValueRef {
         abort_if_not!(self.contains_main, "No main function. Consider creating it");
+        self.vtable["main"]
+    }
+    pub fn exec(mut self, args: &[f64]) -> i32 {
+        let main = self.main();
+        let argc = unsafe { self.wrapper.count_args(main) };
+        abort_if_not!(argc == args.len(), "main expects {} arguments, but got {}", argc, args.len());
         unsafe {
+            self.wrapper.build_entry(main);
+            self.wrapper.finalize_debug_info();
             self.wrapper.run_passes();
-            self.wrapper.jit_main()
+            self.wrapper.jit_main(args)
+        }
+    }
+    /// Runs every test, pairing its name with how it ended
+    pub fn test(mut self) -> Vec<(String, Outcome)> {
+        let results = unsafe {
+            self.wrapper.finalize_debug_info();
+            self.wrapper.run_passes();
+            let symbols: Vec<String> = self.tests.iter().map(|name| format!("{SYMBOL_PREFIX}{name}")).collect();
+            self.wrapper.jit_tests(&symbols)
+        };
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        self.tests
+            .into_iter()
+            .zip(results)
+            .map(|(name, result)| {
+                let outcome = match result {
+                    Some(0.0) => Outcome::Passed,
+                    Some(position) => Outcome::Failed(position as usize),
+                    None => Outcome::Stopped,
+                };
+                (name, outcome)
+            })
+            .collect()
+    }
+    pub fn emit(self, path: &Path) {
+        let main = self.main();
+        unsafe {
+            self.wrapper.build_c_main(main);
+            self.wrapper.finalize_debug_info();
+            self.wrapper.run_passes();
+            self.wrapper.emit_object(path);
         }
     }
 }
 impl Default for Cg {
     fn default() -> Self {
-        Self {
-            vtable: HashMap::new(),
-            wrapper: unsafe { LLVMWrapper::new() },
-            contains_main: false,
+        Self::new(&TargetOptions::default())
+    }
+}
+/// Enums are declared first, as functions take them, and traits before the `impl`s of their methods
+fn declaration_order(ast: &Ast) -> usize {
+    match ast {
+        Ast::EnumNode { .. } => 0,
+        Ast::TraitNode { .. } => 1,
+        _ => 2,
+    }
+}
+/// `ty` with the type parameters replaced by the types they stand for
+fn specialize(ty: &Type, generics: &[String], types: &[String]) -> Type {
+    match ty {
+        Type::Name(name) => match generics.iter().position(|generic| generic == name) {
+            Some(i) => Type::Name(types[i].clone()),
+            None => ty.clone(),
+        },
+        Type::Tuple(elements) => Type::Tuple(elements.iter().map(|ty| specialize(ty, generics, types)).collect()),
+        Type::Reference { mutable, ty } => Type::Reference {
+            mutable: *mutable,
+            ty: Box::new(specialize(ty, generics, types)),
+        },
+        Type::Function(params) => Type::Function(params.iter().map(|ty| specialize(ty, generics, types)).collect()),
+    }
+}
+/// Collects the names of the variables and functions the expression uses
+fn used_names(ast: &Ast, names: &mut Vec<String>) {
+    let mut push = |name: &String| {
+        if !names.contains(name) {
+            names.push(name.clone());
         }
+    };
+    match ast {
+        Ast::IdentifierNode(name) | Ast::ReferenceNode { name, .. } | Ast::DerefNode { name, .. } => push(name),
+        Ast::CallNode(proto) => push(&proto.name),
+        _ => (),
+    }
+    ast.expressions().into_iter().for_each(|ast| used_names(ast, names));
+    ast.blocks().into_iter().flatten().for_each(|ast| used_names(ast, names));
+}
+/// Functions working with vectors, unless a function with the same name is defined
+fn vector_builtin_arity(name: &str) -> Option<usize> {
+    match name {
+        "len" | "pop" => Some(1),
+        "push" => Some(2),
+        _ => None,
+    }
+}
+/// Functions managing memory by hand, unless a function with the same name is defined
+fn memory_builtin_arity(name: &str) -> Option<usize> {
+    match name {
+        "alloc" | "free" | "load" => Some(1),
+        "offset" | "store" => Some(2),
+        _ => None,
+    }
+}
+/// Math functions that are lowered to LLVM intrinsics unless a function with the same name is defined
+fn intrinsic_arity(name: &str) -> Option<usize> {
+    match name {
+        "sqrt" | "sin" | "cos" | "floor" | "ceil" | "fabs" | "exp" | "log" => Some(1),
+        "pow" => Some(2),
+        _ => None,
     }
 }
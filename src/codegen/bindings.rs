@@ -1,31 +1,61 @@
 #![allow(clippy::wildcard_imports)]
 #![allow(clippy::cast_possible_truncation)]
+#![allow(clippy::manual_c_str_literals)]
 pub use llvm_sys::prelude::*;
 
 use std::ffi::CStr;
 use std::ffi::CString;
+use std::path::Path;
 
 use llvm_sys::analysis::LLVMVerifierFailureAction;
 use llvm_sys::analysis::LLVMVerifyFunction;
 use llvm_sys::core::*;
 use llvm_sys::error::LLVMConsumeError;
+use llvm_sys::error::LLVMDisposeErrorMessage;
 use llvm_sys::error::LLVMGetErrorMessage;
 use llvm_sys::execution_engine::LLVMLinkInMCJIT;
 use llvm_sys::orc2::lljit::*;
 use llvm_sys::orc2::*;
 use llvm_sys::target::*;
-use llvm_sys::target_machine::LLVMCreateTargetMachine;
-use llvm_sys::target_machine::LLVMGetDefaultTargetTriple;
-use llvm_sys::target_machine::LLVMGetFirstTarget;
-use llvm_sys::target_machine::LLVMTargetMachineRef;
-use llvm_sys::target_machine::LLVMTargetRef;
-use llvm_sys::target_machine::{LLVMCodeGenOptLevel, LLVMCodeModel, LLVMRelocMode};
+use llvm_sys::target_machine::*;
 use llvm_sys::transforms::pass_builder::*;
-use llvm_sys::LLVMRealPredicate;
+use llvm_sys::{LLVMAttributeFunctionIndex, LLVMIntPredicate, LLVMRealPredicate, LLVMTypeKind};
 
+use super::debug::DebugInfo;
 use crate::{abort, abort_if_not};
+use crate::input::{CursoredFile, TargetOptions};
 use crate::lexer::tokens::ComparisionOpKind;
 
+/// The function JIT runs `main` through
+const ENTRY: &str = "pitusya.entry";
+
+/// Functions, closures, vectors and addresses are stored as quiet NaNs, with their kind above their address.
+/// Arithmetic never makes such NaNs out of numbers, so the kind of a value is checked before it is called, used as a
+/// vector or read through
+#[derive(Clone, Copy)]
+enum Kind {
+    Function = 1,
+    Closure = 2,
+    Vector = 3,
+    /// What `alloc` returns, the only addresses `free` takes
+    Allocation = 4,
+    /// What `offset` returns, the address of any of the allocated numbers
+    Pointer = 5,
+}
+/// The bits of a quiet NaN, which every boxed value has set
+const QUIET_NAN: u64 = 0x7FF8_0000_0000_0000;
+/// How far the kind of a boxed value is shifted, addresses fit into the bits below it
+const KIND_SHIFT: u64 = 48;
+const ADDRESS_MASK: u64 = (1 << KIND_SHIFT) - 1;
+
+/// Fields of the header of a vector, in order
+#[derive(Clone, Copy)]
+enum VectorField {
+    Len,
+    Capacity,
+    Data,
+}
+
 pub struct LLVMWrapper {
     context: LLVMContextRef,
     module: LLVMModuleRef,
@@ -36,6 +66,7 @@ pub struct LLVMWrapper {
     execution_sesion: LLVMOrcExecutionSessionRef,
     jd: LLVMOrcJITDylibRef,
     jit: LLVMOrcLLJITRef,
+    debug: Option<DebugInfo>,
 }
 #[allow(clippy::unused_self)]
 impl LLVMWrapper {
@@ -50,50 +81,226 @@ impl LLVMWrapper {
             execution_sesion: std::ptr::null_mut(),
             jd: std::ptr::null_mut(),
             jit: std::ptr::null_mut(),
+            debug: None,
         }
     }
-    unsafe fn init_target(&mut self) {
-        LLVM_InitializeNativeTarget();
-        LLVM_InitializeNativeAsmPrinter();
-        LLVM_InitializeNativeAsmParser();
-        self.target = LLVMGetFirstTarget();
+    unsafe fn init_target(&mut self, options: &TargetOptions) {
+        let triple = if let Some(triple) = &options.target {
+            LLVM_InitializeAllTargetInfos();
+            LLVM_InitializeAllTargets();
+            LLVM_InitializeAllTargetMCs();
+            LLVM_InitializeAllAsmPrinters();
+            LLVM_InitializeAllAsmParsers();
+            let triple = CString::new(triple.as_str()).unwrap();
+            LLVMNormalizeTargetTriple(triple.as_ptr())
+        } else {
+            LLVM_InitializeNativeTarget();
+            LLVM_InitializeNativeAsmPrinter();
+            LLVM_InitializeNativeAsmParser();
+            LLVMGetDefaultTargetTriple()
+        };
+        let mut error = std::ptr::null_mut();
+        if LLVMGetTargetFromTriple(triple, std::ptr::addr_of_mut!(self.target), std::ptr::addr_of_mut!(error)) != 0 {
+            let msg = CStr::from_ptr(error).to_string_lossy().into_owned();
+            LLVMDisposeMessage(error);
+            abort!("Unsupported target {}: {}", CStr::from_ptr(triple).to_string_lossy(), msg);
+        }
+        let cpu = match options.cpu.as_deref() {
+            Some("native") => LLVMGetHostCPUName(),
+            cpu => LLVMCreateMessage(CString::new(cpu.unwrap_or_default()).unwrap().as_ptr()),
+        };
+        let features = match options.features.as_deref() {
+            Some("native") => LLVMGetHostCPUFeatures(),
+            features => LLVMCreateMessage(CString::new(features.unwrap_or_default()).unwrap().as_ptr()),
+        };
+        // Object files are linked into position independent executables by default
         self.target_machine = LLVMCreateTargetMachine(
             self.target,
-            LLVMGetDefaultTargetTriple(),
-            std::ptr::null(),
-            std::ptr::null(),
+            triple,
+            cpu,
+            features,
             LLVMCodeGenOptLevel::LLVMCodeGenLevelAggressive,
-            LLVMRelocMode::LLVMRelocDefault,
-            LLVMCodeModel::LLVMCodeModelJITDefault,
+            LLVMRelocMode::LLVMRelocPIC,
+            LLVMCodeModel::LLVMCodeModelDefault,
         );
+        LLVMDisposeMessage(cpu);
+        LLVMDisposeMessage(features);
+        LLVMDisposeMessage(triple);
     }
-    pub unsafe fn new() -> Self {
+    pub unsafe fn new(options: &TargetOptions) -> Self {
         let mut wrapper = Self::null();
-        wrapper.init_target();
+        wrapper.init_target(options);
         wrapper.context = LLVMContextCreate();
         wrapper.module = LLVMModuleCreateWithNameInContext("a town with an ocean view\0".as_ptr().cast(), wrapper.context);
         wrapper.builder = LLVMCreateBuilderInContext(wrapper.context);
         wrapper.pass_builder = LLVMCreatePassBuilderOptions();
-        wrapper.create_jit();
         LLVMPassBuilderOptionsSetVerifyEach(wrapper.pass_builder, 1);
-        LLVMSetDataLayout(wrapper.module, LLVMOrcLLJITGetDataLayoutStr(wrapper.jit));
+
+        let triple = LLVMGetTargetMachineTriple(wrapper.target_machine);
+        LLVMSetTarget(wrapper.module, triple);
+        LLVMDisposeMessage(triple);
+        let data_layout = LLVMCreateTargetDataLayout(wrapper.target_machine);
+        LLVMSetModuleDataLayout(wrapper.module, data_layout);
+        LLVMDisposeTargetData(data_layout);
         wrapper
     }
-    pub unsafe fn jit_main(&mut self) -> i32 {
+    pub unsafe fn enable_debug_info(&mut self, source: &CursoredFile) {
+        self.debug = Some(DebugInfo::new(self.context, self.module, source));
+    }
+    pub unsafe fn set_source(&mut self, source: &CursoredFile) {
+        if let Some(debug) = &mut self.debug {
+            debug.set_file(source);
+        }
+    }
+    pub unsafe fn finalize_debug_info(&self) {
+        if let Some(debug) = &self.debug {
+            debug.finalize();
+        }
+    }
+    pub unsafe fn set_location(&self, start: usize) {
+        if let Some(debug) = &self.debug {
+            LLVMSetCurrentDebugLocation2(self.builder, debug.location(self.context, start));
+        }
+    }
+    pub unsafe fn jit_main(&mut self, args: &[f64]) -> i32 {
+        let args = args.as_ptr();
+        let results = self.jit_functions(&[ENTRY], |address| {
+            let p: extern "C" fn(*const f64) -> f64 = std::mem::transmute(address);
+            p(args)
+        });
+        results[0] as i32
+    }
+    /// Runs every test in a process of its own, as failed runtime checks exit. `None` for the tests whose process
+    /// ended before they returned
+    pub unsafe fn jit_tests<S: AsRef<str>>(&mut self, names: &[S]) -> Vec<Option<f64>> {
+        self.jit_functions(names, |address| {
+            let p: extern "C" fn() -> f64 = std::mem::transmute(address);
+            in_child_process(|| p())
+        })
+    }
+    /// Looks up each of the functions one by one, collecting the results of `call`
+    unsafe fn jit_functions<S, F, T>(&mut self, names: &[S], mut call: F) -> Vec<T>
+    where
+        S: AsRef<str>,
+        F: FnMut(LLVMOrcExecutorAddress) -> T,
+    {
+        self.create_jit();
         let resource_tracker = LLVMOrcJITDylibGetDefaultResourceTracker(self.jd);
         let thc = LLVMOrcCreateNewThreadSafeContext();
         let tsm = LLVMOrcCreateNewThreadSafeModule(self.module, thc);
         LLVMOrcLLJITAddLLVMIRModule(self.jit, self.jd, tsm);
 
-        // Horrible...
-        let mut address: LLVMOrcExecutorAddress = 0;
-        LLVMOrcLLJITLookup(self.jit, std::ptr::addr_of_mut!(address), "main\0".as_ptr().cast());
-        let p: fn() -> f64 = std::mem::transmute(address);
-        let res = p() as i32;
+        let results = names
+            .iter()
+            .map(|name| {
+                // Horrible...
+                let name = CString::new(name.as_ref()).unwrap();
+                let mut address: LLVMOrcExecutorAddress = 0;
+                let err = LLVMOrcLLJITLookup(self.jit, std::ptr::addr_of_mut!(address), name.as_ptr());
+                if !err.is_null() {
+                    let msg = LLVMGetErrorMessage(err);
+                    let reason = CStr::from_ptr(msg).to_string_lossy().into_owned();
+                    LLVMDisposeErrorMessage(msg);
+                    abort!("Could not compile {}: {}", name.to_string_lossy(), reason);
+                }
+                call(address)
+            })
+            .collect();
 
         LLVMOrcDisposeThreadSafeContext(thc);
         LLVMOrcResourceTrackerRemove(resource_tracker);
-        res
+        results
+    }
+    /// Generates `pitusya.entry`, which JIT calls `main` through, passing the arguments as an array of doubles
+    pub unsafe fn build_entry(&self, main: LLVMValueRef) {
+        let double = LLVMDoubleTypeInContext(self.context);
+        let mut params = [LLVMPointerType(double, 0)];
+        let name = CString::new(ENTRY).unwrap();
+        let entry = LLVMAddFunction(self.module, name.as_ptr(), LLVMFunctionType(double, params.as_mut_ptr(), 1, 0));
+        LLVMPositionBuilderAtEnd(self.builder, LLVMAppendBasicBlockInContext(self.context, entry, "entry\0".as_ptr().cast()));
+        LLVMSetCurrentDebugLocation2(self.builder, std::ptr::null_mut());
+
+        let args = LLVMGetParam(entry, 0);
+        let mut arguments: Vec<LLVMValueRef> = (0..self.count_args(main))
+            .map(|i| {
+                let mut index = [LLVMConstInt(LLVMInt64TypeInContext(self.context), i as u64, 0)];
+                let arg = LLVMBuildGEP2(self.builder, double, args, index.as_mut_ptr(), 1, "argptr\0".as_ptr().cast());
+                LLVMBuildLoad2(self.builder, double, arg, "arg\0".as_ptr().cast())
+            })
+            .collect();
+        let res = self.call_function(main, arguments.len(), arguments.as_mut_ptr());
+        LLVMBuildRet(self.builder, res);
+    }
+    /// Generates the C `main` of an executable, which parses the command line arguments and passes them to Pitusya's one
+    pub unsafe fn build_c_main(&self, main: LLVMValueRef) {
+        let argc = self.count_args(main);
+
+        let i32_type = LLVMInt32TypeInContext(self.context);
+        let double = LLVMDoubleTypeInContext(self.context);
+        let string = LLVMPointerType(LLVMInt8TypeInContext(self.context), 0);
+        let mut params = [i32_type, LLVMPointerType(string, 0)];
+        let c_main = LLVMAddFunction(self.module, "main\0".as_ptr().cast(), LLVMFunctionType(i32_type, params.as_mut_ptr(), 2, 0));
+        let mut strtod_params = [string, LLVMPointerType(string, 0)];
+        let strtod_type = LLVMFunctionType(double, strtod_params.as_mut_ptr(), 2, 0);
+        let strtod = LLVMAddFunction(self.module, "strtod\0".as_ptr().cast(), strtod_type);
+        let size_type = self.size_type();
+        let mut write_params = [i32_type, string, size_type];
+        let write_type = LLVMFunctionType(size_type, write_params.as_mut_ptr(), 3, 0);
+
+        let entry = LLVMAppendBasicBlockInContext(self.context, c_main, "entry\0".as_ptr().cast());
+        let usage = LLVMAppendBasicBlockInContext(self.context, c_main, "usage\0".as_ptr().cast());
+        LLVMPositionBuilderAtEnd(self.builder, usage);
+        LLVMSetCurrentDebugLocation2(self.builder, std::ptr::null_mut());
+        let message = format!("Expected {argc} numeric argument(s)\n");
+        let length = LLVMConstInt(size_type, message.len() as u64, 0);
+        let message = CString::new(message).unwrap();
+        let message = LLVMBuildGlobalStringPtr(self.builder, message.as_ptr(), "usage\0".as_ptr().cast());
+        // Written to stderr
+        self.call_libc("write", write_type, &mut [LLVMConstInt(i32_type, 2, 0), message, length]);
+        LLVMBuildRet(self.builder, LLVMConstInt(i32_type, 1, 0));
+
+        LLVMPositionBuilderAtEnd(self.builder, entry);
+        let end = LLVMBuildAlloca(self.builder, string, "end\0".as_ptr().cast());
+        let argc_matches = LLVMBuildICmp(
+            self.builder,
+            LLVMIntPredicate::LLVMIntEQ,
+            LLVMGetParam(c_main, 0),
+            LLVMConstInt(i32_type, argc as u64 + 1, 0),
+            "argcmatches\0".as_ptr().cast(),
+        );
+        let mut next = LLVMAppendBasicBlockInContext(self.context, c_main, "parse\0".as_ptr().cast());
+        LLVMBuildCondBr(self.builder, argc_matches, next, usage);
+
+        let mut arguments = Vec::with_capacity(argc);
+        for i in 0..argc {
+            LLVMPositionBuilderAtEnd(self.builder, next);
+            let mut index = [LLVMConstInt(LLVMInt64TypeInContext(self.context), i as u64 + 1, 0)];
+            let arg = LLVMBuildGEP2(self.builder, string, LLVMGetParam(c_main, 1), index.as_mut_ptr(), 1, "argptr\0".as_ptr().cast());
+            let arg = LLVMBuildLoad2(self.builder, string, arg, "arg\0".as_ptr().cast());
+            let mut strtod_args = [arg, end];
+            arguments.push(LLVMBuildCall2(self.builder, strtod_type, strtod, strtod_args.as_mut_ptr(), 2, "num\0".as_ptr().cast()));
+            // The whole argument has to be a number
+            let end = LLVMBuildLoad2(self.builder, string, end, "endptr\0".as_ptr().cast());
+            let last = LLVMBuildLoad2(self.builder, LLVMInt8TypeInContext(self.context), end, "last\0".as_ptr().cast());
+            let consumed = LLVMBuildICmp(
+                self.builder,
+                LLVMIntPredicate::LLVMIntEQ,
+                last,
+                LLVMConstInt(LLVMInt8TypeInContext(self.context), 0, 0),
+                "consumed\0".as_ptr().cast(),
+            );
+            let not_empty = LLVMBuildICmp(self.builder, LLVMIntPredicate::LLVMIntNE, end, arg, "notempty\0".as_ptr().cast());
+            let numeric = LLVMBuildAnd(self.builder, consumed, not_empty, "numeric\0".as_ptr().cast());
+            next = LLVMAppendBasicBlockInContext(self.context, c_main, "parse\0".as_ptr().cast());
+            LLVMBuildCondBr(self.builder, numeric, next, usage);
+        }
+        LLVMPositionBuilderAtEnd(self.builder, next);
+        let res = self.call_function(main, argc, arguments.as_mut_ptr());
+        LLVMBuildRet(self.builder, LLVMBuildFPToSI(self.builder, res, i32_type, "exitcode\0".as_ptr().cast()));
+    }
+    /// The integer type as wide as the pointers of the target, which the C library takes sizes as
+    unsafe fn size_type(&self) -> LLVMTypeRef {
+        LLVMIntPtrTypeInContext(self.context, LLVMGetModuleDataLayout(self.module))
     }
     pub unsafe fn create_jit(&mut self) {
         let err = LLVMOrcCreateLLJIT(std::ptr::addr_of_mut!(self.jit), LLVMOrcCreateLLJITBuilder());
@@ -119,28 +326,104 @@ impl LLVMWrapper {
             self.pass_builder,
         );
     }
+    pub unsafe fn emit_object(&self, path: &Path) {
+        let path = CString::new(path.to_string_lossy().as_bytes()).unwrap();
+        let mut error = std::ptr::null_mut();
+        if LLVMTargetMachineEmitToFile(
+            self.target_machine,
+            self.module,
+            path.as_ptr().cast_mut(),
+            LLVMCodeGenFileType::LLVMObjectFile,
+            std::ptr::addr_of_mut!(error),
+        ) != 0
+        {
+            let msg = CStr::from_ptr(error).to_string_lossy().into_owned();
+            LLVMDisposeMessage(error);
+            abort!("Failed to emit {}: {}", path.to_string_lossy(), msg);
+        }
+    }
     pub unsafe fn declare_function(&self, name: &str, argc: usize) -> LLVMValueRef {
+        let double = LLVMDoubleTypeInContext(self.context);
+        self.declare_function_of_type(name, &vec![double; argc], double)
+    }
+    /// Declares a function taking and returning values of the given types
+    pub unsafe fn declare_function_of_type(&self, name: &str, params: &[LLVMTypeRef], returns: LLVMTypeRef) -> LLVMValueRef {
         let name = CString::new(name).unwrap();
-        let mut arguments = Vec::with_capacity(argc);
-        for _ in 0..argc {
-            arguments.push(LLVMDoubleTypeInContext(self.context));
+        let mut params = params.to_vec();
+        let function_type = LLVMFunctionType(returns, params.as_mut_ptr(), params.len() as u32, 0);
+        LLVMAddFunction(self.module, name.as_ptr(), function_type)
+    }
+    pub unsafe fn number_type(&self) -> LLVMTypeRef {
+        LLVMDoubleTypeInContext(self.context)
+    }
+    /// The type of the parameters taking references, pointers to doubles
+    pub unsafe fn reference_type(&self) -> LLVMTypeRef {
+        LLVMPointerType(LLVMDoubleTypeInContext(self.context), 0)
+    }
+    /// The type functions returning `results` doubles return. Several ones are returned in a struct, which LLVM
+    /// passes the way the target's calling convention does
+    pub unsafe fn tuple_type(&self, results: usize) -> LLVMTypeRef {
+        let double = LLVMDoubleTypeInContext(self.context);
+        if results == 1 {
+            return double;
         }
-        LLVMAddFunction(
-            self.module,
-            name.as_ptr(),
-            LLVMFunctionType(LLVMDoubleTypeInContext(self.context), arguments.as_mut_ptr(), argc as u32, 0),
-        )
+        let mut elements = vec![double; results];
+        LLVMStructTypeInContext(self.context, elements.as_mut_ptr(), results as _, 0)
+    }
+    /// Values of enums are structs named after the enum, holding the tag of the variant and then an array of its
+    /// `fields`, as many as the variant with the most fields has. They are passed around by value like numbers
+    pub unsafe fn create_enum_type(&self, name: &str, fields: usize) -> LLVMTypeRef {
+        let c_name = CString::new(name).unwrap();
+        let ty = LLVMStructCreateNamed(self.context, c_name.as_ptr());
+        let double = LLVMDoubleTypeInContext(self.context);
+        let mut elements = [double, LLVMArrayType(double, fields as _)];
+        LLVMStructSetBody(ty, elements.as_mut_ptr(), 2, 0);
+        ty
     }
-    pub unsafe fn create_function(&self, name: &str, argc: usize) -> LLVMValueRef {
-        let function = self.declare_function(name, argc);
+    pub unsafe fn type_of(&self, value: LLVMValueRef) -> LLVMTypeRef {
+        LLVMTypeOf(value)
+    }
+    /// The name of the type in errors, `f64` or the name of the enum
+    pub unsafe fn type_name(&self, ty: LLVMTypeRef) -> String {
+        if LLVMGetTypeKind(ty) == LLVMTypeKind::LLVMStructTypeKind && !LLVMGetStructName(ty).is_null() {
+            return CStr::from_ptr(LLVMGetStructName(ty)).to_string_lossy().into_owned();
+        }
+        "f64".into()
+    }
+    pub unsafe fn param_types(&self, function: LLVMValueRef) -> Vec<LLVMTypeRef> {
+        let ty = LLVMGlobalGetValueType(function);
+        let mut params = vec![std::ptr::null_mut(); LLVMCountParamTypes(ty) as usize];
+        LLVMGetParamTypes(ty, params.as_mut_ptr());
+        params
+    }
+    pub unsafe fn return_type(&self, function: LLVMValueRef) -> LLVMTypeRef {
+        LLVMGetReturnType(LLVMGlobalGetValueType(function))
+    }
+    /// What the function being generated returns
+    pub unsafe fn current_return_type(&self) -> LLVMTypeRef {
+        self.return_type(self.get_current_function())
+    }
+    /// Starts the body of a declared function
+    pub unsafe fn define_function(&mut self, function: LLVMValueRef, name: &str, argc: usize, start: usize) {
         let entry = LLVMAppendBasicBlockInContext(self.context, function, "entry\0".as_ptr().cast()); // Todo: bad casts
         LLVMPositionBuilderAtEnd(self.builder, entry);
-        function
+        if let Some(debug) = &mut self.debug {
+            debug.create_subprogram(function, name, argc, start);
+        }
+        self.set_location(start);
+    }
+    pub unsafe fn finalize_function(&self, function: LLVMValueRef) {
+        if let Some(debug) = &self.debug {
+            debug.finalize_subprogram();
+        }
+        self.check_function(function);
     }
     pub unsafe fn set_param2function(&self, function: LLVMValueRef, argn: &str, index: usize) -> LLVMValueRef {
-        let argn = CString::new(argn).unwrap();
         let param = LLVMGetParam(function, index as _);
-        LLVMSetValueName2(param, argn.as_ptr(), argn.as_bytes().len());
+        LLVMSetValueName2(param, argn.as_ptr().cast(), argn.len());
+        if let Some(debug) = &self.debug {
+            debug.declare_param(self.context, param, argn, index, LLVMGetInsertBlock(self.builder));
+        }
         param
     }
     #[inline]
@@ -168,6 +451,31 @@ impl LLVMWrapper {
         }
         LLVMPositionBuilderAtEnd(self.builder, merge);
     }
+    /// Jumps to the first block when `cond` is nonzero and to the second otherwise, both meet in the third one
+    pub unsafe fn create_branches(&self, cond: LLVMValueRef) -> (LLVMBasicBlockRef, LLVMBasicBlockRef, LLVMBasicBlockRef) {
+        let function = self.get_current_function();
+        let then = LLVMAppendBasicBlockInContext(self.context, function, "then\0".as_ptr().cast());
+        let otherwise = LLVMAppendBasicBlockInContext(self.context, function, "else\0".as_ptr().cast());
+        let merge = LLVMAppendBasicBlockInContext(self.context, function, "merge\0".as_ptr().cast());
+        LLVMBuildCondBr(self.builder, self.i1cmp(cond, self.gen_fp(0.0), ComparisionOpKind::NeEq), then, otherwise);
+
+        LLVMPositionBuilderAtEnd(self.builder, then);
+        (then, otherwise, merge)
+    }
+    /// Jumps to `merge` unless the branch has returned, and moves on to `next`. Returns the block the branch ended in
+    pub unsafe fn terminate_branch(&self, merge: LLVMBasicBlockRef, ret: bool, next: LLVMBasicBlockRef) -> LLVMBasicBlockRef {
+        let end = LLVMGetInsertBlock(self.builder);
+        if !ret {
+            LLVMBuildBr(self.builder, merge);
+        }
+        LLVMPositionBuilderAtEnd(self.builder, next);
+        end
+    }
+    pub unsafe fn build_phi(&self, values: &mut [LLVMValueRef], blocks: &mut [LLVMBasicBlockRef]) -> LLVMValueRef {
+        let phi = LLVMBuildPhi(self.builder, LLVMTypeOf(values[0]), "iftmp\0".as_ptr().cast());
+        LLVMAddIncoming(phi, values.as_mut_ptr(), blocks.as_mut_ptr(), values.len() as _);
+        phi
+    }
     pub unsafe fn create_loop(&self) -> (LLVMBasicBlockRef, LLVMBasicBlockRef) {
         let function = self.get_current_function();
         let loop_body = LLVMAppendBasicBlockInContext(self.context, function, "loop\0".as_ptr().cast());
@@ -178,31 +486,496 @@ impl LLVMWrapper {
 
         (loop_body, merge)
     }
+    /// Like `create_loop`, but skips the loop entirely when `cond` is zero
+    pub unsafe fn create_guarded_loop(&self, cond: LLVMValueRef) -> (LLVMBasicBlockRef, LLVMBasicBlockRef) {
+        let function = self.get_current_function();
+        let loop_body = LLVMAppendBasicBlockInContext(self.context, function, "loop\0".as_ptr().cast());
+        let merge = LLVMAppendBasicBlockInContext(self.context, function, "merge\0".as_ptr().cast());
+        LLVMBuildCondBr(self.builder, self.i1cmp(cond, self.gen_fp(0.0), ComparisionOpKind::NeEq), loop_body, merge);
+
+        LLVMPositionBuilderAtEnd(self.builder, loop_body);
+
+        (loop_body, merge)
+    }
     pub unsafe fn terminate_loop(&self, cond: LLVMValueRef, loop_body: LLVMBasicBlockRef, merge: LLVMBasicBlockRef, ret: bool) {
         if !ret {
             LLVMBuildCondBr(self.builder, self.i1cmp(cond, self.gen_fp(0.0), ComparisionOpKind::NeEq), loop_body, merge);
         }
         LLVMPositionBuilderAtEnd(self.builder, merge);
     }
+    /// Returns `failure` from the current function if `cond` is zero
+    /// Stops the program with `message` when `value` is 0
+    pub unsafe fn check_nonzero(&self, value: LLVMValueRef, message: &str) {
+        self.build_check(self.i1cmp(value, self.gen_fp(0.0), ComparisionOpKind::NeEq), message);
+    }
+    /// `positive` when `sign` is above 0, `negative` otherwise
+    pub unsafe fn select_by_sign(&self, sign: LLVMValueRef, positive: LLVMValueRef, negative: LLVMValueRef) -> LLVMValueRef {
+        let above = self.i1cmp(sign, self.gen_fp(0.0), ComparisionOpKind::Bigger);
+        LLVMBuildSelect(self.builder, above, positive, negative, "bysign\0".as_ptr().cast())
+    }
+    pub unsafe fn build_assert(&self, cond: LLVMValueRef, failure: LLVMValueRef) {
+        let function = self.get_current_function();
+        let failed = LLVMAppendBasicBlockInContext(self.context, function, "assert_failed\0".as_ptr().cast());
+        let passed = LLVMAppendBasicBlockInContext(self.context, function, "assert_passed\0".as_ptr().cast());
+        LLVMBuildCondBr(self.builder, self.i1cmp(cond, self.gen_fp(0.0), ComparisionOpKind::NeEq), passed, failed);
+        LLVMPositionBuilderAtEnd(self.builder, failed);
+        LLVMBuildRet(self.builder, failure);
+        LLVMPositionBuilderAtEnd(self.builder, passed);
+    }
+    /// Calls `llvm.<name>` overloaded for doubles
+    pub unsafe fn call_intrinsic(&self, name: &str, arguments: &mut [LLVMValueRef]) -> LLVMValueRef {
+        let name = format!("llvm.{name}");
+        let id = LLVMLookupIntrinsicID(name.as_ptr().cast(), name.len());
+        let mut types = [LLVMDoubleTypeInContext(self.context)];
+        let function = LLVMGetIntrinsicDeclaration(self.module, id, types.as_mut_ptr(), types.len());
+        LLVMBuildCall2(
+            self.builder,
+            LLVMIntrinsicGetType(self.context, id, types.as_mut_ptr(), types.len()),
+            function,
+            arguments.as_mut_ptr(),
+            arguments.len() as _,
+            "calltmp\0".as_ptr().cast(),
+        )
+    }
+    /// Functions are passed around as their addresses, boxed
+    pub unsafe fn function_value(&self, function: LLVMValueRef) -> LLVMValueRef {
+        self.boxed(function, Kind::Function)
+    }
+    /// Closures are their environments: arrays holding the function, as `function_value` makes it, and then the
+    /// captured values. The function takes the closure as its first argument. `free` gives the environment back
+    pub unsafe fn create_closure(&self, function: LLVMValueRef, captures: &[LLVMValueRef]) -> LLVMValueRef {
+        let double = LLVMDoubleTypeInContext(self.context);
+        let i64 = LLVMInt64TypeInContext(self.context);
+        let len = LLVMConstInt(i64, captures.len() as u64 + 1, 0);
+        let environment = LLVMBuildArrayMalloc(self.builder, double, len, "environment\0".as_ptr().cast());
+        for (i, value) in std::iter::once(self.function_value(function)).chain(captures.iter().copied()).enumerate() {
+            LLVMBuildStore(self.builder, value, self.element(environment, LLVMConstInt(i64, i as u64, 0)));
+        }
+        self.boxed(environment, Kind::Closure)
+    }
+    /// Loads the `count` values captured by `closure`
+    pub unsafe fn load_captures(&self, closure: LLVMValueRef, count: usize) -> Vec<LLVMValueRef> {
+        let double = LLVMDoubleTypeInContext(self.context);
+        let i64 = LLVMInt64TypeInContext(self.context);
+        let environment = self.unboxed(closure);
+        (1..=count)
+            .map(|i| {
+                let slot = self.element(environment, LLVMConstInt(i64, i as u64, 0));
+                LLVMBuildLoad2(self.builder, double, slot, "capture\0".as_ptr().cast())
+            })
+            .collect()
+    }
+    unsafe fn header(&self, vector: LLVMValueRef) -> LLVMValueRef {
+        self.unboxed(vector)
+    }
+    /// Stores the address of `pointer` in a NaN of the `kind`
+    unsafe fn boxed(&self, pointer: LLVMValueRef, kind: Kind) -> LLVMValueRef {
+        let i64 = LLVMInt64TypeInContext(self.context);
+        let address = LLVMBuildPtrToInt(self.builder, pointer, i64, "address\0".as_ptr().cast());
+        // Moving an address out of its bits must not change the kind
+        let address = LLVMBuildAnd(self.builder, address, LLVMConstInt(i64, ADDRESS_MASK, 0), "address\0".as_ptr().cast());
+        let bits = QUIET_NAN | (kind as u64) << KIND_SHIFT;
+        let boxed = LLVMBuildOr(self.builder, address, LLVMConstInt(i64, bits, 0), "boxed\0".as_ptr().cast());
+        LLVMBuildBitCast(self.builder, boxed, LLVMDoubleTypeInContext(self.context), "boxed\0".as_ptr().cast())
+    }
+    /// Whether `value` is a NaN of the `kind`
+    unsafe fn is_kind(&self, value: LLVMValueRef, kind: Kind) -> LLVMValueRef {
+        let i64 = LLVMInt64TypeInContext(self.context);
+        let bits = LLVMBuildBitCast(self.builder, value, i64, "bits\0".as_ptr().cast());
+        let high = LLVMBuildLShr(self.builder, bits, LLVMConstInt(i64, KIND_SHIFT, 0), "kind\0".as_ptr().cast());
+        let expected = LLVMConstInt(i64, (QUIET_NAN | (kind as u64) << KIND_SHIFT) >> KIND_SHIFT, 0);
+        LLVMBuildICmp(self.builder, LLVMIntPredicate::LLVMIntEQ, high, expected, "iskind\0".as_ptr().cast())
+    }
+    /// Makes the array back from the address stored in a boxed value
+    unsafe fn unboxed(&self, value: LLVMValueRef) -> LLVMValueRef {
+        let i64 = LLVMInt64TypeInContext(self.context);
+        let address = LLVMBuildBitCast(self.builder, value, i64, "address\0".as_ptr().cast());
+        let address = LLVMBuildAnd(self.builder, address, LLVMConstInt(i64, ADDRESS_MASK, 0), "unboxed\0".as_ptr().cast());
+        let ty = LLVMPointerType(LLVMDoubleTypeInContext(self.context), 0);
+        LLVMBuildIntToPtr(self.builder, address, ty, "array\0".as_ptr().cast())
+    }
+    /// Calls the function or closure stored in `value`, the program fails with `message` if it holds neither
+    pub unsafe fn call_value(&self, value: LLVMValueRef, arguments: &[LLVMValueRef], message: &str) -> LLVMValueRef {
+        let is_closure = self.is_kind(value, Kind::Closure);
+        let is_function = self.is_kind(value, Kind::Function);
+        self.build_check(LLVMBuildOr(self.builder, is_closure, is_function, "callable\0".as_ptr().cast()), message);
+
+        let function = self.get_current_function();
+        let closure = LLVMAppendBasicBlockInContext(self.context, function, "closure\0".as_ptr().cast());
+        let plain = LLVMAppendBasicBlockInContext(self.context, function, "function\0".as_ptr().cast());
+        let merge = LLVMAppendBasicBlockInContext(self.context, function, "merge\0".as_ptr().cast());
+        LLVMBuildCondBr(self.builder, is_closure, closure, plain);
+
+        LLVMPositionBuilderAtEnd(self.builder, closure);
+        let environment = self.unboxed(value);
+        let code = LLVMBuildLoad2(self.builder, LLVMDoubleTypeInContext(self.context), environment, "code\0".as_ptr().cast());
+        let with_closure: Vec<LLVMValueRef> = std::iter::once(value).chain(arguments.iter().copied()).collect();
+        let closure_result = self.call_address(code, with_closure);
+        LLVMBuildBr(self.builder, merge);
+
+        LLVMPositionBuilderAtEnd(self.builder, plain);
+        let function_result = self.call_address(value, arguments.to_vec());
+        LLVMBuildBr(self.builder, merge);
+
+        LLVMPositionBuilderAtEnd(self.builder, merge);
+        self.build_phi(&mut [closure_result, function_result], &mut [closure, plain])
+    }
+    /// Calls the function `function_value` has made `value` from
+    unsafe fn call_address(&self, value: LLVMValueRef, mut arguments: Vec<LLVMValueRef>) -> LLVMValueRef {
+        let double = LLVMDoubleTypeInContext(self.context);
+        let mut params = vec![double; arguments.len()];
+        let ty = LLVMFunctionType(double, params.as_mut_ptr(), params.len() as u32, 0);
+        let function = LLVMBuildBitCast(self.builder, self.unboxed(value), LLVMPointerType(ty, 0), "function\0".as_ptr().cast());
+        LLVMBuildCall2(
+            self.builder,
+            ty,
+            function,
+            arguments.as_mut_ptr(),
+            arguments.len() as _,
+            "calltmp\0".as_ptr().cast(),
+        )
+    }
+    /// Vectors are headers holding the length, the capacity and the address of the elements, boxed. The memory comes
+    /// from the C library, as the runtime has no allocator, and `free` gives back the header and the elements
+    pub unsafe fn create_vector(&self, elements: &[LLVMValueRef]) -> LLVMValueRef {
+        let double = LLVMDoubleTypeInContext(self.context);
+        let i64 = LLVMInt64TypeInContext(self.context);
+        let capacity = elements.len().max(4);
+        let header = LLVMBuildArrayMalloc(self.builder, double, LLVMConstInt(i64, 3, 0), "vector\0".as_ptr().cast());
+        let data = LLVMBuildArrayMalloc(self.builder, double, LLVMConstInt(i64, capacity as u64, 0), "data\0".as_ptr().cast());
+        for (i, value) in elements.iter().enumerate() {
+            LLVMBuildStore(self.builder, *value, self.element(data, LLVMConstInt(i64, i as u64, 0)));
+        }
+        let fields = [self.gen_fp(elements.len() as f64), self.gen_fp(capacity as f64), self.address_value(data)];
+        for (i, value) in fields.into_iter().enumerate() {
+            LLVMBuildStore(self.builder, value, self.element(header, LLVMConstInt(i64, i as u64, 0)));
+        }
+        self.boxed(header, Kind::Vector)
+    }
+    /// The program fails with `message` unless `value` is a vector
+    pub unsafe fn check_vector(&self, value: LLVMValueRef, message: &str) {
+        self.build_check(self.is_kind(value, Kind::Vector), message);
+    }
+    pub unsafe fn vector_len(&self, vector: LLVMValueRef) -> LLVMValueRef {
+        self.vector_field(self.header(vector), VectorField::Len)
+    }
+    /// Returns the address of the element at `index`, the program fails with `message` if there is no such element
+    pub unsafe fn vector_element(&self, vector: LLVMValueRef, index: LLVMValueRef, message: &str) -> LLVMValueRef {
+        let header = self.header(vector);
+        let len = self.vector_field(header, VectorField::Len);
+        let above_zero = LLVMBuildFCmp(self.builder, LLVMRealPredicate::LLVMRealOGE, index, self.gen_fp(0.0), "cmptmp\0".as_ptr().cast());
+        let below_len = LLVMBuildFCmp(self.builder, LLVMRealPredicate::LLVMRealOLT, index, len, "cmptmp\0".as_ptr().cast());
+        self.build_check(LLVMBuildAnd(self.builder, above_zero, below_len, "in_bounds\0".as_ptr().cast()), message);
+        let data = self.pointer(self.vector_field(header, VectorField::Data));
+        self.element(data, self.to_index(index))
+    }
+    /// Appends `value`, doubling the capacity when the vector is full. Returns the new length, the program fails with
+    /// `message` if there is no memory left
+    pub unsafe fn vector_push(&self, vector: LLVMValueRef, value: LLVMValueRef, message: &str) -> LLVMValueRef {
+        let header = self.header(vector);
+        let len = self.vector_field(header, VectorField::Len);
+        let capacity = self.vector_field(header, VectorField::Capacity);
+        let full = LLVMBuildFCmp(self.builder, LLVMRealPredicate::LLVMRealOGE, len, capacity, "full\0".as_ptr().cast());
+
+        let function = self.get_current_function();
+        let grow = LLVMAppendBasicBlockInContext(self.context, function, "grow\0".as_ptr().cast());
+        let push = LLVMAppendBasicBlockInContext(self.context, function, "push\0".as_ptr().cast());
+        LLVMBuildCondBr(self.builder, full, grow, push);
+
+        LLVMPositionBuilderAtEnd(self.builder, grow);
+        let capacity = LLVMBuildFMul(self.builder, capacity, self.gen_fp(2.0), "capacity\0".as_ptr().cast());
+        let i8_ptr = LLVMPointerType(LLVMInt8TypeInContext(self.context), 0);
+        let i64 = LLVMInt64TypeInContext(self.context);
+        let data = self.pointer(self.vector_field(header, VectorField::Data));
+        let size = LLVMBuildMul(self.builder, self.to_index(capacity), LLVMConstInt(i64, 8, 0), "size\0".as_ptr().cast());
+        let mut params = [i8_ptr, i64];
+        let mut arguments = [LLVMBuildBitCast(self.builder, data, i8_ptr, "data\0".as_ptr().cast()), size];
+        let data = self.call_libc("realloc", LLVMFunctionType(i8_ptr, params.as_mut_ptr(), 2, 0), &mut arguments);
+        self.build_check(LLVMBuildIsNotNull(self.builder, data, "allocated\0".as_ptr().cast()), message);
+        let data = LLVMBuildBitCast(self.builder, data, LLVMPointerType(LLVMDoubleTypeInContext(self.context), 0), "data\0".as_ptr().cast());
+        LLVMBuildStore(self.builder, capacity, self.vector_field_address(header, VectorField::Capacity));
+        LLVMBuildStore(self.builder, self.address_value(data), self.vector_field_address(header, VectorField::Data));
+        LLVMBuildBr(self.builder, push);
+
+        LLVMPositionBuilderAtEnd(self.builder, push);
+        let data = self.pointer(self.vector_field(header, VectorField::Data));
+        LLVMBuildStore(self.builder, value, self.element(data, self.to_index(len)));
+        let len = self.add(len, self.gen_fp(1.0));
+        LLVMBuildStore(self.builder, len, self.vector_field_address(header, VectorField::Len));
+        len
+    }
+    /// Removes the last element and returns it, the program fails with `message` if the vector is empty
+    pub unsafe fn vector_pop(&self, vector: LLVMValueRef, message: &str) -> LLVMValueRef {
+        let header = self.header(vector);
+        let len = self.vector_field(header, VectorField::Len);
+        self.build_check(LLVMBuildFCmp(self.builder, LLVMRealPredicate::LLVMRealOGT, len, self.gen_fp(0.0), "cmptmp\0".as_ptr().cast()), message);
+        let len = self.sub(len, self.gen_fp(1.0));
+        LLVMBuildStore(self.builder, len, self.vector_field_address(header, VectorField::Len));
+        let data = self.pointer(self.vector_field(header, VectorField::Data));
+        self.deref(self.element(data, self.to_index(len)), "pop")
+    }
+    /// Allocates `count` numbers with malloc, the program fails with `message` if `count` is below one, too big for an
+    /// address or there is no memory left
+    pub unsafe fn alloc(&self, count: LLVMValueRef, message: &str) -> LLVMValueRef {
+        let positive = LLVMBuildFCmp(self.builder, LLVMRealPredicate::LLVMRealOGE, count, self.gen_fp(1.0), "positive\0".as_ptr().cast());
+        let fits = LLVMBuildFCmp(self.builder, LLVMRealPredicate::LLVMRealOLE, count, self.gen_fp(self.max_count()), "fits\0".as_ptr().cast());
+        self.build_check(LLVMBuildAnd(self.builder, positive, fits, "allocatable\0".as_ptr().cast()), message);
+        let i8_ptr = LLVMPointerType(LLVMInt8TypeInContext(self.context), 0);
+        let size_type = self.size_type();
+        let count = LLVMBuildFPToUI(self.builder, count, size_type, "count\0".as_ptr().cast());
+        let size = LLVMBuildMul(self.builder, count, LLVMConstInt(size_type, 8, 0), "size\0".as_ptr().cast());
+        let memory = self.call_libc("malloc", LLVMFunctionType(i8_ptr, [size_type].as_mut_ptr(), 1, 0), &mut [size]);
+        self.build_check(LLVMBuildIsNotNull(self.builder, memory, "allocated\0".as_ptr().cast()), message);
+        self.boxed(memory, Kind::Allocation)
+    }
+    /// The most numbers one allocation may hold, so that their size in bytes fits into an address
+    unsafe fn max_count(&self) -> f64 {
+        let bits = LLVMGetIntTypeWidth(self.size_type()).min(KIND_SHIFT as u32);
+        2f64.powi(bits as i32 - 4)
+    }
+    /// Gives the memory `alloc` has returned, the environment of a closure or a vector with its elements back to free.
+    /// The program fails with `message` if `memory` is none of them
+    pub unsafe fn free(&self, memory: LLVMValueRef, message: &str) {
+        let owned = [Kind::Allocation, Kind::Closure, Kind::Vector].map(|kind| self.is_kind(memory, kind));
+        let owned = owned.into_iter().reduce(|a, b| LLVMBuildOr(self.builder, a, b, "owned\0".as_ptr().cast())).unwrap();
+        self.build_check(owned, message);
+
+        let function = self.get_current_function();
+        let elements = LLVMAppendBasicBlockInContext(self.context, function, "free_elements\0".as_ptr().cast());
+        let header = LLVMAppendBasicBlockInContext(self.context, function, "free_header\0".as_ptr().cast());
+        LLVMBuildCondBr(self.builder, self.is_kind(memory, Kind::Vector), elements, header);
+
+        LLVMPositionBuilderAtEnd(self.builder, elements);
+        self.free_array(self.pointer(self.vector_field(self.header(memory), VectorField::Data)));
+        LLVMBuildBr(self.builder, header);
+
+        LLVMPositionBuilderAtEnd(self.builder, header);
+        self.free_array(self.unboxed(memory));
+    }
+    unsafe fn free_array(&self, array: LLVMValueRef) {
+        let i8_ptr = LLVMPointerType(LLVMInt8TypeInContext(self.context), 0);
+        let mut arguments = [LLVMBuildBitCast(self.builder, array, i8_ptr, "memory\0".as_ptr().cast())];
+        self.call_libc("free", LLVMFunctionType(LLVMVoidTypeInContext(self.context), [i8_ptr].as_mut_ptr(), 1, 0), &mut arguments);
+    }
+    /// The address `count` numbers after `memory`, which may be negative. The program fails with `message` if
+    /// `memory` is not an address, or with `too_far` if `count` moves it further than any allocation reaches
+    pub unsafe fn offset(&self, memory: LLVMValueRef, count: LLVMValueRef, message: &str, too_far: &str) -> LLVMValueRef {
+        self.build_check(self.is_address(memory), message);
+        let limit = self.max_count();
+        let above = LLVMBuildFCmp(self.builder, LLVMRealPredicate::LLVMRealOGT, count, self.gen_fp(-limit), "above\0".as_ptr().cast());
+        let below = LLVMBuildFCmp(self.builder, LLVMRealPredicate::LLVMRealOLT, count, self.gen_fp(limit), "below\0".as_ptr().cast());
+        self.build_check(LLVMBuildAnd(self.builder, above, below, "inrange\0".as_ptr().cast()), too_far);
+        self.boxed(self.element(self.unboxed(memory), self.to_index(count)), Kind::Pointer)
+    }
+    /// Reads the number at `memory`, the program fails with `message` if it is not an address
+    pub unsafe fn load(&self, memory: LLVMValueRef, message: &str) -> LLVMValueRef {
+        self.build_check(self.is_address(memory), message);
+        self.deref(self.unboxed(memory), "load")
+    }
+    /// Writes `value` to `memory`, the program fails with `message` if it is not an address
+    pub unsafe fn store(&self, memory: LLVMValueRef, value: LLVMValueRef, message: &str) {
+        self.build_check(self.is_address(memory), message);
+        LLVMBuildStore(self.builder, value, self.unboxed(memory));
+    }
+    /// Whether `value` is an address `alloc` or `offset` has returned
+    unsafe fn is_address(&self, value: LLVMValueRef) -> LLVMValueRef {
+        let allocation = self.is_kind(value, Kind::Allocation);
+        LLVMBuildOr(self.builder, allocation, self.is_kind(value, Kind::Pointer), "address\0".as_ptr().cast())
+    }
+    /// Makes a value of the enum type `ty`, fields the variant does not have are zero
+    pub unsafe fn create_variant(&self, ty: LLVMTypeRef, tag: usize, fields: &[LLVMValueRef]) -> LLVMValueRef {
+        let mut array = LLVMConstNull(LLVMStructGetTypeAtIndex(ty, 1));
+        for (i, field) in fields.iter().enumerate() {
+            array = LLVMBuildInsertValue(self.builder, array, *field, i as _, "fields\0".as_ptr().cast());
+        }
+        let value = LLVMBuildInsertValue(self.builder, LLVMGetUndef(ty), self.gen_fp(tag as f64), 0, "variant\0".as_ptr().cast());
+        LLVMBuildInsertValue(self.builder, value, array, 1, "variant\0".as_ptr().cast())
+    }
+    pub unsafe fn variant_tag(&self, value: LLVMValueRef) -> LLVMValueRef {
+        LLVMBuildExtractValue(self.builder, value, 0, "tag\0".as_ptr().cast())
+    }
+    /// Takes the first `count` fields out of the enum value
+    pub unsafe fn variant_fields(&self, value: LLVMValueRef, count: usize) -> Vec<LLVMValueRef> {
+        let array = LLVMBuildExtractValue(self.builder, value, 1, "fields\0".as_ptr().cast());
+        (0..count).map(|i| LLVMBuildExtractValue(self.builder, array, i as _, "field\0".as_ptr().cast())).collect()
+    }
+    /// Jumps to the block of the first case equal to `value` converted to an integer, or to the block of the first
+    /// `None` case. The cases after it are never reached. When `exact`, values that are not integers go to the `None`
+    /// case. Without a `None` case, the program fails with `message` if no case is equal. Returns the blocks of the
+    /// cases, positioned in the first one, and the block they meet in
+    pub unsafe fn create_switch(
+        &self,
+        value: LLVMValueRef,
+        cases: &[Option<i64>],
+        exact: bool,
+        message: &str,
+    ) -> (Vec<LLVMBasicBlockRef>, LLVMBasicBlockRef) {
+        let function = self.get_current_function();
+        let (blocks, merge) = self.create_arms(cases.len());
+        let wildcard = cases.iter().position(Option::is_none);
+        let default = match wildcard {
+            Some(i) => blocks[i],
+            None => LLVMAppendBasicBlockInContext(self.context, function, "unmatched\0".as_ptr().cast()),
+        };
+        let mut index = None;
+        if exact {
+            // Converting values that do not fit into 64 bits is undefined, so they are left out first
+            let limit = 2f64.powi(63);
+            let above = LLVMBuildFCmp(self.builder, LLVMRealPredicate::LLVMRealOGE, value, self.gen_fp(-limit), "above\0".as_ptr().cast());
+            let below = LLVMBuildFCmp(self.builder, LLVMRealPredicate::LLVMRealOLT, value, self.gen_fp(limit), "below\0".as_ptr().cast());
+            let fits = LLVMAppendBasicBlockInContext(self.context, function, "fits\0".as_ptr().cast());
+            LLVMBuildCondBr(self.builder, LLVMBuildAnd(self.builder, above, below, "inrange\0".as_ptr().cast()), fits, default);
+            LLVMPositionBuilderAtEnd(self.builder, fits);
+            let integer = self.to_index(value);
+            let back = LLVMBuildSIToFP(self.builder, integer, LLVMDoubleTypeInContext(self.context), "back\0".as_ptr().cast());
+            let whole = LLVMBuildFCmp(self.builder, LLVMRealPredicate::LLVMRealOEQ, back, value, "whole\0".as_ptr().cast());
+            let switch = LLVMAppendBasicBlockInContext(self.context, function, "switch\0".as_ptr().cast());
+            LLVMBuildCondBr(self.builder, whole, switch, default);
+            LLVMPositionBuilderAtEnd(self.builder, switch);
+            index = Some(integer);
+        }
+        let index = index.unwrap_or_else(|| self.to_index(value));
+        let switch = LLVMBuildSwitch(self.builder, index, default, cases.len() as _);
+        let mut added = Vec::with_capacity(cases.len());
+        for (case, block) in cases.iter().zip(&blocks).take(wildcard.unwrap_or(cases.len())) {
+            let case = case.unwrap();
+            if !added.contains(&case) {
+                LLVMAddCase(switch, LLVMConstInt(LLVMInt64TypeInContext(self.context), case as u64, 1), *block);
+                added.push(case);
+            }
+        }
+        if wildcard.is_none() {
+            LLVMPositionBuilderAtEnd(self.builder, default);
+            self.build_failure(message);
+        }
+        LLVMPositionBuilderAtEnd(self.builder, blocks[0]);
+        (blocks, merge)
+    }
+    /// Jumps to the block of the first range `value` is in, or of the first `None` range. The ranges are given by their
+    /// ends, and whether they include the last one. The program fails with `message` if the value is in no range, like
+    /// NaN. Returns the same blocks as `create_switch`
+    pub unsafe fn create_chain(
+        &self,
+        value: LLVMValueRef,
+        ranges: &[Option<(f64, f64, bool)>],
+        message: &str,
+    ) -> (Vec<LLVMBasicBlockRef>, LLVMBasicBlockRef) {
+        let function = self.get_current_function();
+        let (blocks, merge) = self.create_arms(ranges.len());
+        for (range, block) in ranges.iter().zip(&blocks) {
+            let Some((from, to, inclusive)) = *range else {
+                LLVMBuildBr(self.builder, *block);
+                LLVMPositionBuilderAtEnd(self.builder, blocks[0]);
+                return (blocks, merge);
+            };
+            let predicate = if inclusive { LLVMRealPredicate::LLVMRealOLE } else { LLVMRealPredicate::LLVMRealOLT };
+            let above = LLVMBuildFCmp(self.builder, LLVMRealPredicate::LLVMRealOGE, value, self.gen_fp(from), "above\0".as_ptr().cast());
+            let below = LLVMBuildFCmp(self.builder, predicate, value, self.gen_fp(to), "below\0".as_ptr().cast());
+            let next = LLVMAppendBasicBlockInContext(self.context, function, "next\0".as_ptr().cast());
+            LLVMBuildCondBr(self.builder, LLVMBuildAnd(self.builder, above, below, "inrange\0".as_ptr().cast()), *block, next);
+            LLVMPositionBuilderAtEnd(self.builder, next);
+        }
+        self.build_failure(message);
+        LLVMPositionBuilderAtEnd(self.builder, blocks[0]);
+        (blocks, merge)
+    }
+    unsafe fn create_arms(&self, count: usize) -> (Vec<LLVMBasicBlockRef>, LLVMBasicBlockRef) {
+        let function = self.get_current_function();
+        let blocks = (0..count).map(|_| LLVMAppendBasicBlockInContext(self.context, function, "case\0".as_ptr().cast())).collect();
+        let merge = LLVMAppendBasicBlockInContext(self.context, function, "merge\0".as_ptr().cast());
+        (blocks, merge)
+    }
+    unsafe fn vector_field_address(&self, header: LLVMValueRef, field: VectorField) -> LLVMValueRef {
+        self.element(header, LLVMConstInt(LLVMInt64TypeInContext(self.context), field as u64, 0))
+    }
+    unsafe fn vector_field(&self, header: LLVMValueRef, field: VectorField) -> LLVMValueRef {
+        self.deref(self.vector_field_address(header, field), "field")
+    }
+    /// Prints `message` and exits with 1 if `cond` is false
+    unsafe fn build_check(&self, cond: LLVMValueRef, message: &str) {
+        let function = self.get_current_function();
+        let failed = LLVMAppendBasicBlockInContext(self.context, function, "check_failed\0".as_ptr().cast());
+        let passed = LLVMAppendBasicBlockInContext(self.context, function, "check_passed\0".as_ptr().cast());
+        LLVMBuildCondBr(self.builder, cond, passed, failed);
+
+        LLVMPositionBuilderAtEnd(self.builder, failed);
+        self.build_failure(message);
+
+        LLVMPositionBuilderAtEnd(self.builder, passed);
+    }
+    /// Ends the current block by printing `message` and exiting with 1
+    unsafe fn build_failure(&self, message: &str) {
+        let i32 = LLVMInt32TypeInContext(self.context);
+        let i8_ptr = LLVMPointerType(LLVMInt8TypeInContext(self.context), 0);
+        let message = CString::new(message).unwrap();
+        let mut arguments = [LLVMBuildGlobalStringPtr(self.builder, message.as_ptr(), "message\0".as_ptr().cast())];
+        self.call_libc("puts", LLVMFunctionType(i32, [i8_ptr].as_mut_ptr(), 1, 0), &mut arguments);
+        let mut arguments = [LLVMConstInt(i32, 1, 0)];
+        self.call_libc("exit", LLVMFunctionType(LLVMVoidTypeInContext(self.context), [i32].as_mut_ptr(), 1, 0), &mut arguments);
+        LLVMBuildUnreachable(self.builder);
+    }
+    /// Calls a function of the C library, which the program may have declared with other types
+    unsafe fn call_libc(&self, name: &str, ty: LLVMTypeRef, arguments: &mut [LLVMValueRef]) -> LLVMValueRef {
+        let c_name = CString::new(name).unwrap();
+        let mut function = LLVMGetNamedFunction(self.module, c_name.as_ptr());
+        if function.is_null() {
+            function = LLVMAddFunction(self.module, c_name.as_ptr(), ty);
+        } else if LLVMGlobalGetValueType(function) != ty {
+            function = LLVMConstBitCast(function, LLVMPointerType(ty, 0));
+        }
+        LLVMBuildCall2(self.builder, ty, function, arguments.as_mut_ptr(), arguments.len() as _, "\0".as_ptr().cast())
+    }
+    unsafe fn element(&self, array: LLVMValueRef, index: LLVMValueRef) -> LLVMValueRef {
+        let mut indices = [index];
+        LLVMBuildGEP2(self.builder, LLVMDoubleTypeInContext(self.context), array, indices.as_mut_ptr(), 1, "element\0".as_ptr().cast())
+    }
+    unsafe fn to_index(&self, value: LLVMValueRef) -> LLVMValueRef {
+        LLVMBuildFPToSI(self.builder, value, LLVMInt64TypeInContext(self.context), "index\0".as_ptr().cast())
+    }
+    /// Stores the address of an array of doubles in the bits of a double
+    unsafe fn address_value(&self, array: LLVMValueRef) -> LLVMValueRef {
+        let address = LLVMBuildPtrToInt(self.builder, array, LLVMInt64TypeInContext(self.context), "address\0".as_ptr().cast());
+        LLVMBuildBitCast(self.builder, address, LLVMDoubleTypeInContext(self.context), "addrvalue\0".as_ptr().cast())
+    }
+    /// Makes the array of doubles `address_value` has stored in `value` back
+    unsafe fn pointer(&self, value: LLVMValueRef) -> LLVMValueRef {
+        let address = LLVMBuildBitCast(self.builder, value, LLVMInt64TypeInContext(self.context), "address\0".as_ptr().cast());
+        let ty = LLVMPointerType(LLVMDoubleTypeInContext(self.context), 0);
+        LLVMBuildIntToPtr(self.builder, address, ty, "array\0".as_ptr().cast())
+    }
     pub unsafe fn count_args(&self, function: LLVMValueRef) -> usize {
         LLVMCountParams(function) as _
     }
     pub unsafe fn check_function(&self, function: LLVMValueRef) {
         LLVMVerifyFunction(function, LLVMVerifierFailureAction::LLVMAbortProcessAction);
     }
+    /// Calls a function of the program. LLVM is told it is not the C library function it may be named after, so a
+    /// `fn sqrt(x)` is not computed as the square root
     pub unsafe fn call_function(&self, callee: LLVMValueRef, argc: usize, arguments: *mut LLVMValueRef) -> LLVMValueRef {
-        LLVMBuildCall2(
+        let call = LLVMBuildCall2(
             self.builder,
             LLVMGlobalGetValueType(callee),
             callee,
             arguments,
             argc as _,
             "calltmp\0".as_ptr().cast(),
-        )
+        );
+        let kind = LLVMGetEnumAttributeKindForName("nobuiltin".as_ptr().cast(), "nobuiltin".len());
+        LLVMAddCallSiteAttribute(call, LLVMAttributeFunctionIndex, LLVMCreateEnumAttribute(self.context, kind, 0));
+        call
     }
-    pub unsafe fn create_var(&self, value: LLVMValueRef, name: &str) -> LLVMValueRef {
-        let name = CString::new(name).unwrap();
-        let var = LLVMBuildAlloca(self.builder, LLVMDoubleTypeInContext(self.context), name.as_ptr());
+    pub unsafe fn create_global(&self, name: &str, initial: f64) -> LLVMValueRef {
+        let c_name = CString::new(name).unwrap();
+        let global = LLVMAddGlobal(self.module, LLVMDoubleTypeInContext(self.context), c_name.as_ptr());
+        LLVMSetInitializer(global, self.gen_fp(initial));
+        global
+    }
+    pub unsafe fn create_var(&self, value: LLVMValueRef, name: &str, start: usize) -> LLVMValueRef {
+        let c_name = CString::new(name).unwrap();
+        let var = LLVMBuildAlloca(self.builder, LLVMTypeOf(value), c_name.as_ptr());
+        if let Some(debug) = &self.debug {
+            debug.declare_var(self.context, var, name, start, LLVMGetInsertBlock(self.builder));
+        }
         self.assign2var(value, var);
         var
     }
@@ -211,11 +984,31 @@ impl LLVMWrapper {
     }
     pub unsafe fn deref(&self, v: LLVMValueRef, name: &str) -> LLVMValueRef {
         let name = CString::new(name).unwrap();
-        LLVMBuildLoad2(self.builder, LLVMDoubleTypeInContext(self.context), v, name.as_ptr())
+        LLVMBuildLoad2(self.builder, self.stored_type(v), v, name.as_ptr())
+    }
+    /// The type of what `pointer` points to. Variables may hold values of enums, everything else holds numbers
+    pub unsafe fn stored_type(&self, pointer: LLVMValueRef) -> LLVMTypeRef {
+        if LLVMIsAAllocaInst(pointer).is_null() {
+            LLVMDoubleTypeInContext(self.context)
+        } else {
+            LLVMGetAllocatedType(pointer)
+        }
     }
     pub unsafe fn build_return(&self, v: LLVMValueRef) -> LLVMValueRef {
         LLVMBuildRet(self.builder, v)
     }
+    /// Returns the values in the struct `tuple_type` has made
+    pub unsafe fn build_return_tuple(&self, values: &[LLVMValueRef]) -> LLVMValueRef {
+        let mut tuple = LLVMGetUndef(self.tuple_type(values.len()));
+        for (i, value) in values.iter().enumerate() {
+            tuple = LLVMBuildInsertValue(self.builder, tuple, *value, i as _, "tuple\0".as_ptr().cast());
+        }
+        LLVMBuildRet(self.builder, tuple)
+    }
+    /// Takes the values out of a struct a function has returned
+    pub unsafe fn tuple_elements(&self, tuple: LLVMValueRef, count: usize) -> Vec<LLVMValueRef> {
+        (0..count).map(|i| LLVMBuildExtractValue(self.builder, tuple, i as _, "element\0".as_ptr().cast())).collect()
+    }
     pub unsafe fn gen_fp(&self, n: f64) -> LLVMValueRef {
         LLVMConstReal(LLVMDoubleTypeInContext(self.context), n)
     }
@@ -236,6 +1029,9 @@ impl LLVMWrapper {
     pub unsafe fn div(&self, lhs: LLVMValueRef, rhs: LLVMValueRef) -> LLVMValueRef {
         LLVMBuildFDiv(self.builder, lhs, rhs, "divtmp\0".as_ptr().cast())
     }
+    pub unsafe fn rem(&self, lhs: LLVMValueRef, rhs: LLVMValueRef) -> LLVMValueRef {
+        LLVMBuildFRem(self.builder, lhs, rhs, "remtmp\0".as_ptr().cast())
+    }
     pub unsafe fn cmp(&self, lhs: LLVMValueRef, rhs: LLVMValueRef, op: ComparisionOpKind) -> LLVMValueRef {
         let cmp = self.i1cmp(lhs, rhs, op);
         LLVMBuildSIToFP(
@@ -277,7 +1073,16 @@ impl LLVMWrapper {
             None,
             std::ptr::null_mut()
         );
-        abort_if_not!(err.is_null(), "Link error!");
+        if !err.is_null() {
+            let msg = LLVMGetErrorMessage(err);
+            let reason = CStr::from_ptr(msg).to_string_lossy().into_owned();
+            LLVMDisposeErrorMessage(msg);
+            abort!(
+                "Could not load the runtime library {}: {}. Make sure it is in the library search path",
+                pitusya_std.to_string_lossy(),
+                reason
+            );
+        }
         self.link(proc_syms_gen);
     }
     #[inline]
@@ -287,8 +1092,12 @@ impl LLVMWrapper {
 }
 impl Drop for LLVMWrapper {
     fn drop(&mut self) {
+        self.debug = None;
         unsafe {
-            LLVMOrcDisposeLLJIT(self.jit);
+            if !self.jit.is_null() {
+                LLVMOrcDisposeLLJIT(self.jit);
+            }
+            LLVMDisposeTargetMachine(self.target_machine);
             LLVMDisposePassBuilderOptions(self.pass_builder);
             LLVMDisposeBuilder(self.builder);
             LLVMContextDispose(self.context);
@@ -308,3 +1117,28 @@ impl Into<LLVMRealPredicate> for ComparisionOpKind {
         }
     }
 }
+/// Calls `f` in a forked process, so that exiting or crashing only ends that one. `None` when it did
+unsafe fn in_child_process<F: FnOnce() -> f64>(f: F) -> Option<f64> {
+    let mut pipe = [0; 2];
+    abort_if_not!(libc::pipe(pipe.as_mut_ptr()) == 0, "Could not create a pipe to run a test");
+    // Otherwise the child writes out what is buffered as well
+    libc::fflush(std::ptr::null_mut());
+    match libc::fork() {
+        -1 => abort!("Could not start a process to run a test"),
+        0 => {
+            libc::close(pipe[0]);
+            let result = f().to_ne_bytes();
+            libc::write(pipe[1], result.as_ptr().cast(), result.len());
+            libc::fflush(std::ptr::null_mut());
+            libc::_exit(0)
+        }
+        child => {
+            libc::close(pipe[1]);
+            let mut result = [0u8; 8];
+            let read = libc::read(pipe[0], result.as_mut_ptr().cast(), result.len());
+            libc::close(pipe[0]);
+            libc::waitpid(child, std::ptr::null_mut(), 0);
+            (read == 8).then(|| f64::from_ne_bytes(result))
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use std::fs;
+
+use llvm_sys::core::{LLVMAddModuleFlag, LLVMConstInt, LLVMInt32TypeInContext, LLVMValueAsMetadata};
+use llvm_sys::debuginfo::*;
+use llvm_sys::LLVMModuleFlagBehavior;
+
+use super::bindings::*;
+use crate::input::CursoredFile;
+
+const DW_ATE_FLOAT: LLVMDWARFTypeEncoding = 0x04;
+
+pub struct DebugInfo {
+    builder: LLVMDIBuilderRef,
+    file: LLVMMetadataRef,
+    double: LLVMMetadataRef,
+    subprogram: LLVMMetadataRef,
+    subprogram_line: u32,
+    lines: Vec<usize>,
+}
+impl DebugInfo {
+    pub unsafe fn new(context: LLVMContextRef, module: LLVMModuleRef, source: &CursoredFile) -> Self {
+        let builder = LLVMCreateDIBuilder(module);
+        let file = create_file(builder, source);
+
+        let producer = concat!("pitusya ", env!("CARGO_PKG_VERSION"));
+        LLVMDIBuilderCreateCompileUnit(
+            builder,
+            LLVMDWARFSourceLanguage::LLVMDWARFSourceLanguageC,
+            file,
+            producer.as_ptr().cast(),
+            producer.len(),
+            0,
+            std::ptr::null(),
+            0,
+            0,
+            std::ptr::null(),
+            0,
+            LLVMDWARFEmissionKind::LLVMDWARFEmissionKindFull,
+            0,
+            0,
+            0,
+            std::ptr::null(),
+            0,
+            std::ptr::null(),
+            0,
+        );
+        let double = LLVMDIBuilderCreateBasicType(builder, "double".as_ptr().cast(), 6, 64, DW_ATE_FLOAT, LLVMDIFlagZero);
+
+        let int = LLVMInt32TypeInContext(context);
+        let flags = [
+            ("Debug Info Version", LLVMDebugMetadataVersion()),
+            ("Dwarf Version", 4),
+        ];
+        for (key, value) in flags {
+            LLVMAddModuleFlag(
+                module,
+                LLVMModuleFlagBehavior::LLVMModuleFlagBehaviorWarning,
+                key.as_ptr().cast(),
+                key.len(),
+                LLVMValueAsMetadata(LLVMConstInt(int, u64::from(value), 0)),
+            );
+        }
+
+        Self {
+            builder,
+            file,
+            double,
+            subprogram: std::ptr::null_mut(),
+            subprogram_line: 0,
+            lines: line_starts(source),
+        }
+    }
+    /// Makes the functions created from now on point into `source`
+    pub unsafe fn set_file(&mut self, source: &CursoredFile) {
+        self.file = create_file(self.builder, source);
+        self.lines = line_starts(source);
+    }
+    /// Converts a position in the source into a 1-based line and column
+    fn line_and_column(&self, start: usize) -> (u32, u32) {
+        let line = self.lines.partition_point(|l| *l <= start);
+        (line as u32, (start - self.lines[line - 1] + 1) as u32)
+    }
+    pub unsafe fn create_subprogram(&mut self, function: LLVMValueRef, name: &str, argc: usize, start: usize) {
+        let mut types = vec![self.double; argc + 1];
+        let ty = LLVMDIBuilderCreateSubroutineType(self.builder, self.file, types.as_mut_ptr(), types.len() as _, LLVMDIFlagZero);
+        let (line, _) = self.line_and_column(start);
+        self.subprogram = LLVMDIBuilderCreateFunction(
+            self.builder,
+            self.file,
+            name.as_ptr().cast(),
+            name.len(),
+            name.as_ptr().cast(),
+            name.len(),
+            self.file,
+            line,
+            ty,
+            0,
+            1,
+            line,
+            LLVMDIFlagPrototyped,
+            0,
+        );
+        self.subprogram_line = line;
+        LLVMSetSubprogram(function, self.subprogram);
+    }
+    pub unsafe fn finalize_subprogram(&self) {
+        LLVMDIBuilderFinalizeSubprogram(self.builder, self.subprogram);
+    }
+    pub unsafe fn location(&self, context: LLVMContextRef, start: usize) -> LLVMMetadataRef {
+        let (line, column) = self.line_and_column(start);
+        LLVMDIBuilderCreateDebugLocation(context, line, column, self.subprogram, std::ptr::null_mut())
+    }
+    pub unsafe fn declare_var(&self, context: LLVMContextRef, var: LLVMValueRef, name: &str, start: usize, block: LLVMBasicBlockRef) {
+        let (line, _) = self.line_and_column(start);
+        let info = LLVMDIBuilderCreateAutoVariable(
+            self.builder,
+            self.subprogram,
+            name.as_ptr().cast(),
+            name.len(),
+            self.file,
+            line,
+            self.double,
+            1,
+            LLVMDIFlagZero,
+            0,
+        );
+        let expr = LLVMDIBuilderCreateExpression(self.builder, std::ptr::null_mut(), 0);
+        LLVMDIBuilderInsertDeclareAtEnd(self.builder, var, info, expr, self.location(context, start), block);
+    }
+    pub unsafe fn declare_param(&self, context: LLVMContextRef, param: LLVMValueRef, name: &str, index: usize, block: LLVMBasicBlockRef) {
+        let info = LLVMDIBuilderCreateParameterVariable(
+            self.builder,
+            self.subprogram,
+            name.as_ptr().cast(),
+            name.len(),
+            index as u32 + 1,
+            self.file,
+            self.subprogram_line,
+            self.double,
+            1,
+            LLVMDIFlagZero,
+        );
+        let expr = LLVMDIBuilderCreateExpression(self.builder, std::ptr::null_mut(), 0);
+        let location = LLVMDIBuilderCreateDebugLocation(context, self.subprogram_line, 0, self.subprogram, std::ptr::null_mut());
+        LLVMDIBuilderInsertDbgValueAtEnd(self.builder, param, info, expr, location, block);
+    }
+    pub unsafe fn finalize(&self) {
+        LLVMDIBuilderFinalize(self.builder);
+    }
+}
+unsafe fn create_file(builder: LLVMDIBuilderRef, source: &CursoredFile) -> LLVMMetadataRef {
+    let path = fs::canonicalize(&source.name).unwrap_or_else(|_| source.name.clone());
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let directory = path.parent().map(|d| d.to_string_lossy().into_owned()).unwrap_or_default();
+    LLVMDIBuilderCreateFile(builder, name.as_ptr().cast(), name.len(), directory.as_ptr().cast(), directory.len())
+}
+pub(super) fn line_starts(source: &CursoredFile) -> Vec<usize> {
+    std::iter::once(0)
+        .chain(source.content.iter().enumerate().filter(|(_, c)| **c == '\n').map(|(i, _)| i + 1))
+        .collect()
+}
+impl Drop for DebugInfo {
+    fn drop(&mut self) {
+        unsafe {
+            LLVMDisposeDIBuilder(self.builder);
+        }
+    }
+}
@@ -1,11 +1,33 @@
 use super::bindings::*;
 
+#[derive(Clone, Copy)]
 pub struct Variable {
     pub(super) value: LLVMValueRef,
     pub(super) is_function_arg: bool,
+    /// Number of parameters of the function the variable holds, if it is known
+    pub(super) arity: Option<usize>,
+    /// Whether the variable holds a vector, which cannot be used as a number
+    pub(super) vector: bool,
+    /// Whether the variable is a mutable reference or a reference, the value is then a pointer
+    pub(super) reference: Option<bool>,
 }
 impl Variable {
     pub fn new(value: LLVMValueRef, is_function_arg: bool) -> Self {
-        Self { value, is_function_arg }
+        Self {
+            value,
+            is_function_arg,
+            arity: None,
+            vector: false,
+            reference: None,
+        }
+    }
+    pub fn with_arity(self, arity: Option<usize>) -> Self {
+        Self { arity, ..self }
+    }
+    pub fn with_vector(self, vector: bool) -> Self {
+        Self { vector, ..self }
+    }
+    pub fn with_reference(self, reference: Option<bool>) -> Self {
+        Self { reference, ..self }
     }
 }
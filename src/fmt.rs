@@ -0,0 +1,501 @@
+use std::collections::VecDeque;
+
+use crate::ast::{parser, Ast, Pattern, Proto};
+use crate::input::CursoredFile;
+use crate::lexer::next_token_with_comments;
+use crate::lexer::tokens::{BinaryOperatorKind, ComparisionOpKind, LiteralKind, OperatorKind, TokenKind};
+
+const INDENT: &str = "    ";
+
+/// Pretty-prints a whole file, keeping the comments close to where they were
+pub fn format(input: &mut CursoredFile) -> String {
+    let mut comments = VecDeque::new();
+    let mut blocks = Vec::new();
+    let mut open = Vec::new();
+    loop {
+        let token = next_token_with_comments(input);
+        match token.kind {
+            TokenKind::Comment(comment) => comments.push_back((token.start, comment)),
+            TokenKind::Operator(OperatorKind::LCurly) => {
+                open.push(blocks.len());
+                blocks.push(token.start);
+            }
+            TokenKind::Operator(OperatorKind::RCurly) => {
+                if let Some(i) = open.pop() {
+                    blocks[i] = token.start;
+                }
+            }
+            TokenKind::EOF => break,
+            _ => (),
+        }
+    }
+    input.cursor.replace(0);
+    let asts = parser::parse(input);
+
+    let mut formatter = Formatter {
+        out: String::new(),
+        comments,
+        block_ends: blocks.into(),
+        source: input.as_ref(),
+        indent: 0,
+        last_line: None,
+    };
+    let mut previous: Option<&Ast> = None;
+    for ast in &asts {
+        if let Some(previous) = previous {
+            if !matches!(
+                (previous, ast),
+                (Ast::ExternNode(_), Ast::ExternNode(_))
+                    | (Ast::ImportNode { .. }, Ast::ImportNode { .. })
+                    | (Ast::GlobalNode { .. }, Ast::GlobalNode { .. })
+            ) {
+                formatter.out.push('\n');
+            }
+        }
+        formatter.item(ast);
+        previous = Some(ast);
+    }
+    formatter.comments(usize::MAX);
+    formatter.out
+}
+
+struct Formatter<'a> {
+    out: String,
+    comments: VecDeque<(usize, String)>,
+    /// Positions of the closing curly braces in the order the blocks are opened
+    block_ends: VecDeque<usize>,
+    source: &'a [char],
+    indent: usize,
+    last_line: Option<usize>,
+}
+impl Formatter<'_> {
+    fn line(&self, position: usize) -> usize {
+        self.source[..position.min(self.source.len())].iter().filter(|c| **c == '\n').count()
+    }
+    /// Writes out every comment before `position`. The ones sharing a line with the last written code stay trailing
+    fn comments(&mut self, position: usize) {
+        while let Some((start, _)) = self.comments.front() {
+            if *start >= position {
+                break;
+            }
+            let (start, comment) = self.comments.pop_front().unwrap();
+            if self.last_line == Some(self.line(start)) && self.out.ends_with('\n') {
+                self.out.pop();
+                self.out.push(' ');
+            } else {
+                self.blank_line(start);
+                self.push_indent();
+            }
+            self.out.push_str(&comment);
+            self.out.push('\n');
+        }
+    }
+    /// Keeps one blank line above the line of `position` if there was at least one, except right after an opening brace
+    fn blank_line(&mut self, position: usize) {
+        let newlines = self.source[..position.min(self.source.len())]
+            .iter()
+            .rev()
+            .skip_while(|c| **c != '\n')
+            .take_while(|c| c.is_whitespace())
+            .filter(|c| **c == '\n')
+            .count();
+        if newlines > 1 && self.out.ends_with('\n') && !self.out.ends_with("\n\n") && !self.out.ends_with("{\n") {
+            self.out.push('\n');
+        }
+    }
+    fn push_indent(&mut self) {
+        for _ in 0..self.indent {
+            self.out.push_str(INDENT);
+        }
+    }
+    fn item(&mut self, ast: &Ast) {
+        if let Some(start) = ast.start() {
+            self.comments(start);
+            self.blank_line(start);
+            self.last_line = Some(self.line(start));
+        }
+        match ast {
+            Ast::FunctionNode { proto, body, .. } | Ast::TestNode { proto, body } => {
+                self.out.push_str(match ast {
+                    Ast::TestNode { .. } => "test fn ",
+                    Ast::FunctionNode { public: true, .. } => "pub fn ",
+                    _ => "fn ",
+                });
+                self.proto(proto);
+                self.out.push(' ');
+                self.block(body);
+                self.out.push('\n');
+            }
+            Ast::ExternNode(proto) => {
+                self.out.push_str("extern ");
+                self.proto(proto);
+                self.out.push('\n');
+            }
+            Ast::GlobalNode { name, value, mutable, public, .. } => {
+                if *public {
+                    self.out.push_str("pub ");
+                }
+                self.out.push_str(if *mutable { "static " } else { "const " });
+                self.out.push_str(name);
+                self.out.push_str(" = ");
+                self.expression(value);
+                self.out.push('\n');
+            }
+            Ast::ImportNode { path, .. } => {
+                self.out.push_str("import \"");
+                self.out.push_str(path);
+                self.out.push_str("\"\n");
+            }
+            Ast::EnumNode { name, variants, public, .. } => {
+                if *public {
+                    self.out.push_str("pub ");
+                }
+                self.out.push_str("enum ");
+                self.out.push_str(name);
+                self.out.push(' ');
+                let end = self.open_braces();
+                for variant in variants {
+                    self.comments(variant.start);
+                    self.last_line = Some(self.line(variant.start));
+                    self.push_indent();
+                    self.out.push_str(&variant.name);
+                    self.names(&variant.fields);
+                    self.out.push_str(",\n");
+                }
+                self.close_braces(end);
+                self.out.push('\n');
+            }
+            Ast::TraitNode { name, methods, public, .. } => {
+                if *public {
+                    self.out.push_str("pub ");
+                }
+                self.out.push_str("trait ");
+                self.out.push_str(name);
+                self.out.push(' ');
+                let end = self.open_braces();
+                for proto in methods {
+                    self.comments(proto.start);
+                    self.last_line = Some(self.line(proto.start));
+                    self.push_indent();
+                    self.out.push_str("fn ");
+                    self.proto(proto);
+                    self.out.push('\n');
+                }
+                self.close_braces(end);
+                self.out.push('\n');
+            }
+            Ast::ImplNode { name, target, functions, .. } => {
+                self.out.push_str(&format!("impl {name} for {target} "));
+                let end = self.open_braces();
+                for (i, function) in functions.iter().enumerate() {
+                    if i != 0 {
+                        self.out.push('\n');
+                    }
+                    if let Some(start) = function.start() {
+                        self.comments(start);
+                    }
+                    self.push_indent();
+                    self.item(function);
+                }
+                self.close_braces(end);
+                self.out.push('\n');
+            }
+            _ => (),
+        }
+    }
+    /// Opens curly braces that do not contain a block, returning where they close
+    fn open_braces(&mut self) -> usize {
+        self.out.push_str("{\n");
+        self.indent += 1;
+        self.block_ends.pop_front().unwrap_or(usize::MAX)
+    }
+    fn close_braces(&mut self, end: usize) {
+        self.comments(end);
+        self.indent -= 1;
+        self.push_indent();
+        self.out.push('}');
+        self.last_line = Some(self.line(end));
+    }
+    /// Writes `(names)`, or nothing when there are no names
+    fn names(&mut self, names: &[String]) {
+        if !names.is_empty() {
+            self.out.push('(');
+            self.out.push_str(&names.join(", "));
+            self.out.push(')');
+        }
+    }
+    fn block(&mut self, body: &[Ast]) {
+        let end = self.block_ends.pop_front().unwrap_or(usize::MAX);
+        self.out.push_str("{\n");
+        self.indent += 1;
+        for (i, ast) in body.iter().enumerate() {
+            self.statement(ast, body.get(i + 1));
+        }
+        self.comments(end);
+        self.indent -= 1;
+        self.push_indent();
+        self.out.push('}');
+        self.last_line = Some(self.line(end));
+    }
+    /// Writes a statement on its own line. It keeps its `;` when the `next` one would otherwise continue it
+    fn statement(&mut self, ast: &Ast, next: Option<&Ast>) {
+        if let Some(start) = ast.start() {
+            self.comments(start);
+            self.blank_line(start);
+            self.last_line = Some(self.line(start));
+        }
+        self.push_indent();
+        match ast {
+            Ast::LetNode { assignee, value, .. } => {
+                self.out.push_str("let ");
+                self.out.push_str(assignee);
+                self.out.push_str(" = ");
+                self.expression(value);
+            }
+            Ast::DestructureNode { names, value, .. } => {
+                self.out.push_str("let ");
+                self.names(names);
+                self.out.push_str(" = ");
+                self.expression(value);
+            }
+            Ast::IfNode { .. } => self.if_else(ast),
+            Ast::MatchNode { .. } => self.match_arms(ast),
+            Ast::WhileNode { condition, body, .. } => {
+                self.out.push_str("while ");
+                self.expression(condition);
+                self.out.push(' ');
+                self.block(body);
+            }
+            Ast::ForNode { variable, from, to, inclusive, step, body, .. } => {
+                self.out.push_str("for ");
+                self.out.push_str(variable);
+                self.out.push_str(" in ");
+                self.expression(from);
+                self.out.push_str(if *inclusive { "..=" } else { ".." });
+                self.expression(to);
+                if let Some(step) = step {
+                    self.out.push_str(" step ");
+                    self.expression(step);
+                }
+                self.out.push(' ');
+                self.block(body);
+            }
+            Ast::ForEachNode { variable, vector, body, .. } => {
+                self.out.push_str("for ");
+                self.out.push_str(variable);
+                self.out.push_str(" in ");
+                self.expression(vector);
+                self.out.push(' ');
+                self.block(body);
+            }
+            Ast::RetNode { value, .. } => {
+                self.out.push_str("ret ");
+                self.expression(value);
+            }
+            Ast::DeferNode { value, .. } => {
+                self.out.push_str("defer ");
+                self.expression(value);
+            }
+            expression => self.expression(expression),
+        }
+        let ends_with_expression = !matches!(
+            ast,
+            Ast::IfNode { .. } | Ast::MatchNode { .. } | Ast::WhileNode { .. } | Ast::ForNode { .. } | Ast::ForEachNode { .. }
+        );
+        // A call or a multiplication would take in a next statement starting with `(` or `*`
+        if ends_with_expression && next.is_some_and(|next| matches!(leftmost(next), Ast::UnitNode(_) | Ast::TupleNode { .. } | Ast::DerefNode { .. })) {
+            self.out.push(';');
+        }
+        self.out.push('\n');
+    }
+    fn expression(&mut self, ast: &Ast) {
+        match ast {
+            Ast::ValueNode(LiteralKind::Num(n)) => self.out.push_str(&n.to_string()),
+            Ast::ValueNode(LiteralKind::Str(s)) => self.out.push_str(s),
+            Ast::IdentifierNode(ident) => self.out.push_str(ident),
+            Ast::ReferenceNode { name, mutable, .. } => {
+                self.out.push_str(if *mutable { "&mut " } else { "&" });
+                self.out.push_str(name);
+            }
+            Ast::DerefNode { name, .. } => {
+                self.out.push('*');
+                self.out.push_str(name);
+            }
+            Ast::UnitNode(unit) => {
+                self.out.push('(');
+                self.expression(unit);
+                self.out.push(')');
+            }
+            Ast::TupleNode { elements, .. } => {
+                self.out.push('(');
+                for (i, element) in elements.iter().enumerate() {
+                    if i != 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.expression(element);
+                }
+                self.out.push(')');
+            }
+            Ast::BinaryNode { left, right, op, .. } => {
+                self.expression(left);
+                self.out.push(' ');
+                self.out.push_str(operator(*op));
+                self.out.push(' ');
+                self.expression(right);
+            }
+            Ast::CompoundAssignNode { assignee, value, op, .. } => {
+                self.expression(assignee);
+                self.out.push(' ');
+                self.out.push_str(operator(*op));
+                self.out.push_str("= ");
+                self.expression(value);
+            }
+            Ast::CallNode(proto) => self.proto(proto),
+            Ast::IfNode { .. } => self.if_else(ast),
+            Ast::MatchNode { .. } => self.match_arms(ast),
+            Ast::VectorNode { elements, .. } => {
+                self.out.push('[');
+                for (i, element) in elements.iter().enumerate() {
+                    if i != 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.expression(element);
+                }
+                self.out.push(']');
+            }
+            Ast::IndexNode { vector, index, .. } => {
+                self.expression(vector);
+                self.out.push('[');
+                self.expression(index);
+                self.out.push(']');
+            }
+            Ast::MethodNode { receiver, method, args, .. } => {
+                self.expression(receiver);
+                self.out.push('.');
+                self.out.push_str(method);
+                self.out.push('(');
+                for (i, arg) in args.iter().enumerate() {
+                    if i != 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.expression(arg);
+                }
+                self.out.push(')');
+            }
+            Ast::ClosureNode { params, body, .. } => {
+                self.out.push('|');
+                self.out.push_str(&params.join(", "));
+                self.out.push_str("| ");
+                self.expression(body);
+            }
+            _ => (),
+        }
+    }
+    fn match_arms(&mut self, ast: &Ast) {
+        let Ast::MatchNode { value, arms, .. } = ast else {
+            return;
+        };
+        self.out.push_str("match ");
+        self.expression(value);
+        self.out.push(' ');
+        let end = self.open_braces();
+        for arm in arms {
+            self.comments(arm.start);
+            self.last_line = Some(self.line(arm.start));
+            self.push_indent();
+            match &arm.pattern {
+                Pattern::Variant { name, bindings } => {
+                    self.out.push_str(name);
+                    self.names(bindings);
+                }
+                Pattern::Number(n) => self.out.push_str(&n.to_string()),
+                Pattern::Range { from, to, inclusive } => {
+                    self.out.push_str(&from.to_string());
+                    self.out.push_str(if *inclusive { "..=" } else { ".." });
+                    self.out.push_str(&to.to_string());
+                }
+                Pattern::Wildcard => self.out.push('_'),
+            }
+            self.out.push_str(" => ");
+            if arm.expression {
+                self.expression(&arm.body[0]);
+                self.out.push(',');
+            } else {
+                self.block(&arm.body);
+            }
+            self.out.push('\n');
+        }
+        self.close_braces(end);
+    }
+    fn if_else(&mut self, ast: &Ast) {
+        let Ast::IfNode { condition, body, else_body, .. } = ast else {
+            return;
+        };
+        self.out.push_str("if ");
+        self.expression(condition);
+        self.out.push(' ');
+        self.block(body);
+        let Some(else_body) = else_body else {
+            return;
+        };
+        self.out.push_str(" else ");
+        if let [chained @ Ast::IfNode { start, .. }] = else_body.as_slice() {
+            // `else if` is kept as is, unlike an `if` that is alone in an `else` block
+            if self.source[..*start].iter().rev().find(|c| !c.is_whitespace()) == Some(&'e') {
+                self.if_else(chained);
+                return;
+            }
+        }
+        self.block(else_body);
+    }
+    fn proto(&mut self, proto: &Proto) {
+        self.out.push_str(&proto.name);
+        if !proto.generics.is_empty() {
+            self.out.push_str(&format!("<{}>", proto.generics.join(", ")));
+        }
+        self.out.push('(');
+        for (i, arg) in proto.args.iter().enumerate() {
+            if i != 0 {
+                self.out.push_str(", ");
+            }
+            self.expression(arg);
+            if let Some(Some(ty)) = proto.types.get(i) {
+                self.out.push_str(&format!(": {ty}"));
+            }
+        }
+        self.out.push(')');
+        if let Some(returns) = &proto.returns {
+            self.out.push_str(&format!(" -> {returns}"));
+        }
+    }
+}
+/// The expression a statement starts with when it is written out
+fn leftmost(ast: &Ast) -> &Ast {
+    match ast {
+        Ast::BinaryNode { left: inner, .. }
+        | Ast::CompoundAssignNode { assignee: inner, .. }
+        | Ast::IndexNode { vector: inner, .. }
+        | Ast::MethodNode { receiver: inner, .. } => leftmost(inner),
+        _ => ast,
+    }
+}
+fn operator(op: BinaryOperatorKind) -> &'static str {
+    match op {
+        BinaryOperatorKind::Assigment => "=",
+        BinaryOperatorKind::Addition => "+",
+        BinaryOperatorKind::Subtraction => "-",
+        BinaryOperatorKind::Multiplication => "*",
+        BinaryOperatorKind::Division => "/",
+        BinaryOperatorKind::Remainder => "%",
+        BinaryOperatorKind::Power => "**",
+        BinaryOperatorKind::FloorDivision => "~/",
+        BinaryOperatorKind::Comparision(cmp) => match cmp {
+            ComparisionOpKind::Equals => "==",
+            ComparisionOpKind::NeEq => "!=",
+            ComparisionOpKind::Bigger => ">",
+            ComparisionOpKind::BiggerOrEq => ">=",
+            ComparisionOpKind::Less => "<",
+            ComparisionOpKind::LessOrEq => "<=",
+        },
+    }
+}
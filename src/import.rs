@@ -0,0 +1,411 @@
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::abort_with_message;
+use crate::ast::{parser, Ast, Pattern, Proto, Type};
+use crate::input::CursoredFile;
+use crate::lexer::tokens::{Token, TokenKind};
+use crate::lexer::{next_token, token_at};
+
+const PRELUDE: &str = include_str!("prelude.pt");
+/// Name of the module of the prelude
+const PRELUDE_MODULE: &str = "prelude";
+
+pub struct Module {
+    pub file: CursoredFile,
+    pub asts: Vec<Ast>,
+}
+
+/// Parses `root` along with every file it imports. Each file is loaded once and comes after the files it imports,
+/// so `root` is the last one. Tests are only kept for `root`. The prelude goes first if it is included
+///
+/// Every imported file is a module named after the file. Its functions are called as `module::function` from the
+/// other files, which is the name they get in the generated code, and only `pub fn`s can be called this way. Names
+/// a file neither defines nor imports are looked up in the prelude
+pub fn load(root: CursoredFile, prelude: bool) -> Vec<Module> {
+    let mut loader = Loader::default();
+    if prelude {
+        let file = CursoredFile::from_source("<prelude>", PRELUDE);
+        loader.module_files.insert(PRELUDE_MODULE.into(), file.name.clone());
+        loader.load(file, Some(PRELUDE_MODULE.into()));
+    }
+    loader.load(root, None);
+    let mut modules = loader.modules;
+    let imported = modules.len() - 1;
+    for module in &mut modules[..imported] {
+        module.asts.retain(|ast| !matches!(ast, Ast::TestNode { .. }));
+    }
+    modules
+}
+
+#[derive(Default)]
+struct Loader {
+    modules: Vec<Module>,
+    loaded: HashSet<PathBuf>,
+    /// Files that are being loaded, each one imports the next
+    stack: Vec<PathBuf>,
+    /// Which file every module comes from
+    module_files: HashMap<String, PathBuf>,
+    /// Functions, globals, enums and traits of every loaded module, and whether they are public
+    exports: HashMap<String, HashMap<String, bool>>,
+    /// Where every function is defined, and its arity if it is an extern
+    defined: HashMap<String, (PathBuf, Option<usize>)>,
+    /// Start of the statement being resolved, errors about the names in it point there
+    statement: Cell<usize>,
+}
+impl Loader {
+    fn load(&mut self, mut input: CursoredFile, module: Option<String>) {
+        let path = canonical(&input.name);
+        self.stack.push(path.clone());
+        let mut asts = parser::parse(&mut input);
+
+        let directory = input.name.parent().unwrap_or(Path::new("")).to_path_buf();
+        let mut imports = HashSet::new();
+        for ast in &asts {
+            let Ast::ImportNode { path, start } = ast else {
+                continue;
+            };
+            let mut imported = directory.join(path);
+            if imported.extension().is_none() {
+                imported.set_extension("pt");
+            }
+            let path_token = |input: &mut CursoredFile| {
+                token_at(input, *start);
+                next_token(input)
+            };
+            let Ok(content) = fs::read_to_string(&imported) else {
+                let token = path_token(&mut input);
+                abort_with_message!(token, input, format!("cannot read {}", imported.display()))
+            };
+            let canonical = canonical(&imported);
+            if let Some(i) = self.stack.iter().position(|p| *p == canonical) {
+                let cycle: Vec<String> = self.stack[i..].iter().chain([&canonical]).map(|p| display_name(p)).collect();
+                let token = path_token(&mut input);
+                abort_with_message!(token, input, format!("import cycle: {}", cycle.join(" -> ")))
+            }
+            let name = imported.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+            match self.module_files.get(&name) {
+                Some(file) if *file != canonical => {
+                    let help = format!("module `{name}` is already imported from {}", file.display());
+                    let token = path_token(&mut input);
+                    abort_with_message!(token, input, help)
+                }
+                Some(_) => (),
+                None => {
+                    self.module_files.insert(name.clone(), canonical.clone());
+                }
+            }
+            if !self.loaded.contains(&canonical) {
+                self.load(CursoredFile::from_source(imported, content), Some(name.clone()));
+            }
+            imports.insert(name);
+        }
+
+        let items: HashMap<String, bool> = asts
+            .iter()
+            .filter_map(|ast| match ast {
+                Ast::FunctionNode { proto, public, .. } => Some((proto.name.clone(), *public)),
+                Ast::GlobalNode { name, public, .. } | Ast::EnumNode { name, public, .. } | Ast::TraitNode { name, public, .. } => {
+                    Some((name.clone(), *public))
+                }
+                _ => None,
+            })
+            .collect();
+        let scope = Scope {
+            module: module.as_deref(),
+            items: &items,
+            imports: &imports,
+            locals: HashSet::new(),
+        };
+        for ast in &mut asts {
+            self.statement.set(ast.start().unwrap_or_default());
+            self.resolve(ast, &scope, &mut input);
+        }
+        if let Some(module) = module {
+            self.exports.insert(module, items);
+        }
+
+        asts.retain(|ast| self.define(ast, &path, &mut input));
+        self.stack.pop();
+        self.loaded.insert(path);
+        self.modules.push(Module { file: input, asts });
+    }
+    /// Gives the functions and globals of the module, and everything referring to them, the names they have in the
+    /// generated code
+    fn resolve(&self, ast: &mut Ast, scope: &Scope, input: &mut CursoredFile) {
+        match ast {
+            Ast::FunctionNode { proto, body, .. } | Ast::TestNode { proto, body } => {
+                if let Some(module) = scope.module {
+                    proto.name = format!("{module}::{}", proto.name);
+                }
+                self.resolve_types(proto, scope, input);
+                self.resolve_body(proto, body, scope, input);
+            }
+            Ast::ExternNode(proto) => self.resolve_types(proto, scope, input),
+            Ast::ImplNode { name, target, functions, start } => {
+                for (name, kind) in [(name, "trait"), (target, "enum")] {
+                    if let Err(help) = self.qualify(name, kind, scope) {
+                        let token = token_at(input, *start);
+                        abort_with_message!(token, input, help)
+                    }
+                }
+                // Methods are named after the enum in the generated code, which already is qualified
+                for function in functions {
+                    if let Ast::FunctionNode { proto, body, .. } = function {
+                        self.resolve_types(proto, scope, input);
+                        self.resolve_body(proto, body, scope, input);
+                    }
+                }
+            }
+            Ast::MethodNode { receiver, args, .. } => {
+                self.resolve(receiver, scope, input);
+                args.iter_mut().for_each(|ast| self.resolve(ast, scope, input));
+            }
+            Ast::GlobalNode { name, value, .. } => {
+                if let Some(module) = scope.module {
+                    *name = format!("{module}::{name}");
+                }
+                self.resolve(value, scope, input);
+            }
+            Ast::EnumNode { name, .. } => {
+                if let Some(module) = scope.module {
+                    *name = format!("{module}::{name}");
+                }
+            }
+            Ast::TraitNode { name, methods, .. } => {
+                if let Some(module) = scope.module {
+                    *name = format!("{module}::{name}");
+                }
+                methods.iter_mut().for_each(|proto| self.resolve_types(proto, scope, input));
+            }
+            Ast::MatchNode { value, arms, .. } => {
+                self.resolve(value, scope, input);
+                for arm in arms {
+                    let mut bindings: &[String] = &[];
+                    if let Pattern::Variant { name, bindings: names } = &mut arm.pattern {
+                        if let Err(help) = self.qualify(name, "enum", scope) {
+                            let token = token_at(input, arm.start);
+                            abort_with_message!(token, input, help)
+                        }
+                        bindings = names;
+                    }
+                    self.resolve_block(&mut arm.body, bindings, scope, input);
+                }
+            }
+            Ast::CallNode(proto) => {
+                proto.args.iter_mut().for_each(|ast| self.resolve(ast, scope, input));
+                if let Err(help) = self.qualify(&mut proto.name, "function", scope) {
+                    let token = token_at(input, proto.start);
+                    abort_with_message!(token, input, help)
+                }
+            }
+            Ast::IdentifierNode(name) => {
+                let written = name.clone();
+                if let Err(help) = self.qualify(name, "function or global", scope) {
+                    let token = self.name_token(input, &written);
+                    abort_with_message!(token, input, help)
+                }
+            }
+            Ast::CompoundAssignNode { assignee, value, .. } => {
+                match &mut **assignee {
+                    Ast::IdentifierNode(name) => {
+                        let written = name.clone();
+                        if let Err(help) = self.qualify(name, "global", scope) {
+                            let token = self.name_token(input, &written);
+                            abort_with_message!(token, input, help)
+                        }
+                    }
+                    assignee => self.resolve(assignee, scope, input),
+                }
+                self.resolve(value, scope, input);
+            }
+            Ast::ReferenceNode { name, start, .. } => {
+                if let Err(help) = self.qualify(name, "global", scope) {
+                    let token = token_at(input, *start);
+                    abort_with_message!(token, input, help)
+                }
+            }
+            Ast::BinaryNode { left, right, .. } => {
+                self.resolve(left, scope, input);
+                self.resolve(right, scope, input);
+            }
+            Ast::LetNode { value, .. }
+            | Ast::DestructureNode { value, .. }
+            | Ast::RetNode { value, .. }
+            | Ast::DeferNode { value, .. } => {
+                self.resolve(value, scope, input);
+            }
+            Ast::VectorNode { elements, .. } | Ast::TupleNode { elements, .. } => {
+                elements.iter_mut().for_each(|ast| self.resolve(ast, scope, input));
+            }
+            Ast::IndexNode { vector, index, .. } => {
+                self.resolve(vector, scope, input);
+                self.resolve(index, scope, input);
+            }
+            Ast::ForEachNode { variable, vector, body, .. } => {
+                self.resolve(vector, scope, input);
+                self.resolve_block(body, std::slice::from_ref(variable), scope, input);
+            }
+            Ast::ClosureNode { params, body, .. } => {
+                let mut locals = scope.locals.clone();
+                locals.extend(params.iter().cloned());
+                let scope = Scope { locals, ..*scope };
+                self.resolve(body, &scope, input);
+            }
+            Ast::UnitNode(unit) => self.resolve(unit, scope, input),
+            Ast::IfNode { condition, body, else_body, .. } => {
+                self.resolve(condition, scope, input);
+                self.resolve_block(body, &[], scope, input);
+                if let Some(else_body) = else_body {
+                    self.resolve_block(else_body, &[], scope, input);
+                }
+            }
+            Ast::WhileNode { condition, body, .. } => {
+                self.resolve(condition, scope, input);
+                self.resolve_block(body, &[], scope, input);
+            }
+            Ast::ForNode { variable, from, to, step, body, .. } => {
+                self.resolve(from, scope, input);
+                self.resolve(to, scope, input);
+                if let Some(step) = step {
+                    self.resolve(step, scope, input);
+                }
+                self.resolve_block(body, std::slice::from_ref(variable), scope, input);
+            }
+            _ => (),
+        }
+    }
+    /// Qualifies the enums the parameters and the result of the function are annotated with
+    fn resolve_types(&self, proto: &mut Proto, scope: &Scope, input: &mut CursoredFile) {
+        let Proto { generics, types, returns, start, .. } = proto;
+        let mut names = Vec::new();
+        for ty in types.iter_mut().flatten().chain(returns) {
+            type_names(ty, &mut names);
+        }
+        for name in names.into_iter().filter(|name| *name != "f64" && !generics.contains(name)) {
+            if let Err(help) = self.qualify(name, "enum", scope) {
+                let token = token_at(input, *start);
+                abort_with_message!(token, input, help)
+            }
+        }
+    }
+    /// Resolves the body of a function, where its parameters shadow the items of the module
+    fn resolve_body(&self, proto: &Proto, body: &mut [Ast], scope: &Scope, input: &mut CursoredFile) {
+        let params: Vec<String> = proto.args.iter().filter_map(|arg| match arg {
+            Ast::IdentifierNode(arg) => Some(arg.clone()),
+            _ => None,
+        }).collect();
+        self.resolve_block(body, &params, scope, input);
+    }
+    /// Resolves the statements of a block, where `names` and the variables the block defines shadow the items of the
+    /// module. A variable does so from its `let` until the end of the block
+    fn resolve_block(&self, body: &mut [Ast], names: &[String], scope: &Scope, input: &mut CursoredFile) {
+        let mut locals = scope.locals.clone();
+        locals.extend(names.iter().cloned());
+        let mut scope = Scope { locals, ..*scope };
+        for ast in body {
+            if let Some(start) = ast.start() {
+                self.statement.set(start);
+            }
+            self.resolve(ast, &scope, input);
+            match ast {
+                Ast::LetNode { assignee, .. } => {
+                    scope.locals.insert(assignee.clone());
+                }
+                Ast::DestructureNode { names, .. } => scope.locals.extend(names.iter().cloned()),
+                _ => (),
+            }
+        }
+    }
+    /// The token of the name used in the statement being resolved, as names carry no position of their own
+    fn name_token(&self, input: &mut CursoredFile, name: &str) -> Token {
+        let start = self.statement.get();
+        let line = input.content[..start].iter().rposition(|c| *c == '\n').map_or(0, |i| i + 1);
+        let mut token = token_at(input, line);
+        while token.kind != TokenKind::EOF {
+            if matches!(&token.kind, TokenKind::Identifier(found) if found == name) {
+                return token;
+            }
+            token = next_token(input);
+        }
+        token_at(input, start)
+    }
+    /// Checks that `module::item` names are accessible, and qualifies the names of the module's own items
+    fn qualify(&self, name: &mut String, kind: &str, scope: &Scope) -> Result<(), String> {
+        // Methods numbers implement are called as `f64::method`
+        if let Some((module, item)) = name.split_once("::").filter(|(first, _)| *first != "f64" && !scope.items.contains_key(*first)) {
+            // `module::enum::variant` is reached through the enum
+            let (item, kind) = item.split_once("::").map_or((item, kind), |(item, _)| (item, "enum"));
+            return match self.exports.get(module).and_then(|exports| exports.get(item)) {
+                _ if !scope.imports.contains(module) => Err(format!("module `{module}` is not imported")),
+                None => Err(format!("module `{module}` has no {kind} `{item}`")),
+                Some(false) => Err(format!("{kind} `{item}` is private to module `{module}`")),
+                Some(true) => Ok(()),
+            };
+        }
+        let item = name.split_once("::").map_or(name.as_str(), |(item, _)| item);
+        if scope.locals.contains(item) {
+            return Ok(());
+        }
+        if scope.items.contains_key(item) {
+            if let Some(module) = scope.module {
+                *name = format!("{module}::{name}");
+            }
+        } else if self.exports.get(PRELUDE_MODULE).is_some_and(|exports| exports.get(item) == Some(&true)) {
+            *name = format!("{PRELUDE_MODULE}::{name}");
+        }
+        Ok(())
+    }
+    /// Returns false for externs that have already been declared the same way by another file
+    fn define(&mut self, ast: &Ast, path: &Path, input: &mut CursoredFile) -> bool {
+        let (proto, arity) = match ast {
+            Ast::ExternNode(proto) => (proto, Some(proto.args.len())),
+            Ast::FunctionNode { proto, .. } | Ast::TestNode { proto, .. } => (proto, None),
+            Ast::GlobalNode { name, start, .. } | Ast::EnumNode { name, start, .. } | Ast::TraitNode { name, start, .. } => {
+                if let Some((file, _)) = self.defined.get(name) {
+                    let help = format!("`{name}` is already defined in {}", display_name(file));
+                    let token = token_at(input, *start);
+                    abort_with_message!(token, input, help)
+                }
+                self.defined.insert(name.clone(), (path.to_path_buf(), None));
+                return true;
+            }
+            _ => return true,
+        };
+        let Some((file, defined_arity)) = self.defined.get(&proto.name) else {
+            self.defined.insert(proto.name.clone(), (path.to_path_buf(), arity));
+            return true;
+        };
+        if arity.is_some() && arity == *defined_arity {
+            return false;
+        }
+        let help = format!("`{}` is already defined in {}", proto.name, display_name(file));
+        let token = token_at(input, proto.start);
+        abort_with_message!(token, input, help)
+    }
+}
+/// What the names used in a file can refer to
+struct Scope<'a> {
+    module: Option<&'a str>,
+    /// Functions, globals, enums and traits of the module, and whether they are public
+    items: &'a HashMap<String, bool>,
+    imports: &'a HashSet<String>,
+    /// Parameters and variables in scope where the names are used, they shadow the globals
+    locals: HashSet<String>,
+}
+/// Collects the names the type is made of
+fn type_names<'a>(ty: &'a mut Type, names: &mut Vec<&'a mut String>) {
+    match ty {
+        Type::Name(name) => names.push(name),
+        Type::Tuple(types) | Type::Function(types) => types.iter_mut().for_each(|ty| type_names(ty, names)),
+        Type::Reference { ty, .. } => type_names(ty, names),
+    }
+}
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+fn display_name(path: &Path) -> String {
+    path.file_name().map_or_else(|| path.display().to_string(), |name| name.to_string_lossy().into_owned())
+}
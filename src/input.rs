@@ -2,7 +2,7 @@ use std::cell::RefCell;
 use std::fs;
 use std::path::PathBuf;
 
-pub use clap::{Parser, Subcommand};
+pub use clap::{Args, Parser, Subcommand};
 
 use crate::abort;
 
@@ -12,10 +12,62 @@ use crate::abort;
     version,
     about = "
 The Pitusya Programming Language (=^ ◡ ^=)
-"
+",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
 )]
 pub struct Cli {
-    file: PathBuf,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[arg(required = true)]
+    pub file: Option<PathBuf>,
+    /// Numeric arguments passed to `main`
+    #[arg(last = true)]
+    pub args: Vec<String>,
+    /// Write an object file instead of running the program
+    #[arg(short, long, global = true)]
+    pub output: Option<PathBuf>,
+    /// Emit debug information
+    #[arg(short = 'g', global = true)]
+    pub debug: bool,
+    /// Do not include the prelude with `abs`, `min`, `max`, `clamp` and `with_alloc`
+    #[arg(long, global = true)]
+    pub no_prelude: bool,
+    #[command(flatten)]
+    pub target: TargetOptions,
+}
+#[derive(Subcommand)]
+pub enum Command {
+    /// Format source files in place
+    Fmt {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Only check whether the files are formatted, exiting with a non-zero code if not
+        #[arg(long)]
+        check: bool,
+    },
+    /// Run the language server over stdio
+    Lsp,
+    /// Run the file, passing the arguments after `--` to `main`
+    Run {
+        file: PathBuf,
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
+    /// Run every `test fn` of the file
+    Test { file: PathBuf },
+}
+#[derive(Args, Debug, Default, Clone)]
+pub struct TargetOptions {
+    /// Target triple to generate code for, e.g. `aarch64-unknown-linux-gnu` (defaults to the host)
+    #[arg(long, global = true)]
+    pub target: Option<String>,
+    /// Target CPU, `native` stands for the host one
+    #[arg(long, global = true)]
+    pub cpu: Option<String>,
+    /// Target features, e.g. `+avx2,-sse4.1`, `native` stands for the host ones
+    #[arg(long, global = true)]
+    pub features: Option<String>,
 }
 #[derive(Debug, PartialEq, Clone)]
 pub struct CursoredFile {
@@ -24,19 +76,18 @@ pub struct CursoredFile {
     pub(crate) content_str: String,
     pub(crate) cursor: RefCell<usize>,
 }
-impl From<Cli> for PathBuf {
-    fn from(value: Cli) -> Self {
-        value.file
-    }
-}
 impl CursoredFile {
     pub fn new<P: Into<PathBuf>>(file_name: P) -> Self {
         let file_name = file_name.into();
         let Ok(content) = fs::read_to_string(&file_name) else {
             abort!("File {} does not exist!", file_name.display());
         };
+        Self::from_source(file_name, content)
+    }
+    pub fn from_source<P: Into<PathBuf>, S: Into<String>>(name: P, content: S) -> Self {
+        let content = content.into();
         Self {
-            name: file_name,
+            name: name.into(),
             content: content.chars().collect(),
             content_str: content,
             cursor: RefCell::new(0),
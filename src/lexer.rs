@@ -12,9 +12,17 @@ type Handler = dyn Fn(&str) -> TokenKind + Sync + Send;
 static SPEC: OnceLock<Vec<(Regex, Box<Handler>)>> = OnceLock::new();
 
 pub fn next_token(input: &mut CursoredFile) -> Token {
+    loop {
+        let token = next_token_with_comments(input);
+        if !matches!(token.kind, TokenKind::Comment(_)) {
+            return token;
+        }
+    }
+}
+pub fn next_token_with_comments(input: &mut CursoredFile) -> Token {
     input.skip_spaces();
     if input.out_of_bounds() {
-        return Token::eof(input.content.len() - 1);
+        return Token::eof(input.content.len().saturating_sub(1));
     }
     let content = input.as_ref();
     let curs = input.get_cursor();
@@ -36,26 +44,36 @@ pub fn next_token(input: &mut CursoredFile) -> Token {
     Token::undefined(c, start)
 }
 
+/// Lexes the token starting at `start` again, e.g. to point at it in an error message
+pub fn token_at(input: &mut CursoredFile, start: usize) -> Token {
+    input.cursor.replace(start);
+    next_token(input)
+}
+
 fn get_specification() -> &'static Vec<(Regex, Box<Handler>)> {
     SPEC.get_or_init(|| {
         vec![
+            (
+                Regex::new(r"//[^\n]*").unwrap(),
+                Box::new(|s| TokenKind::Comment(s.trim_end().into())),
+            ),
             (
                 Regex::new(r"([0-9]*[.])?[0-9]+").unwrap(),
                 Box::new(|s| TokenKind::Literal(LiteralKind::Num(s.parse().unwrap()))),
             ),
             (
-                Regex::new("\"[a-zA-Z0-0]+\"").unwrap(),
+                Regex::new("\"[^\"\n]*\"").unwrap(),
                 Box::new(|s| TokenKind::Literal(LiteralKind::Str(s.into()))),
             ),
             (
-                Regex::new(r"[_a-zA-Z0-9]+").unwrap(),
+                Regex::new(r"[_a-zA-Z0-9]+(::[_a-zA-Z0-9]+)*").unwrap(),
                 Box::new(|s| match KeywordKind::try_from(s) {
                     Ok(keyword) => TokenKind::Keyword(keyword),
                     _ => TokenKind::Identifier(s.into()),
                 }),
             ),
             (
-                Regex::new(r"<=|>=|==|!=|=|\+|-|\*|/|<|>|;|,|\(|\)|\{|\}").unwrap(),
+                Regex::new(r"\.\.=|\.\.|\.|<=|>=|=>|->|==|!=|\+=|-=|\*=|/=|=|\+|-|\*\*|\*|~/|/|%|<|>|\||&|:|;|,|\(|\)|\{|\}|\[|\]").unwrap(),
                 Box::new(|s| match OperatorKind::try_from(s) {
                     Ok(operator) => TokenKind::Operator(operator),
                     _ => TokenKind::Undefined(s.chars().next().unwrap()),
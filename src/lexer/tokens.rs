@@ -11,6 +11,7 @@ pub enum TokenKind {
     Keyword(KeywordKind),
     Literal(LiteralKind),
     Identifier(String),
+    Comment(String),
     Undefined(char),
     EOF
 }
@@ -25,7 +26,16 @@ pub enum OperatorKind {
     RBracket, // ]
     Semicol,  // ;
     Coma,     // ,
-    Binary(BinaryOperatorKind)
+    Range,    // ..
+    RangeInclusive, // ..=
+    Pipe,     // |
+    FatArrow, // =>
+    Arrow,    // ->
+    Colon,    // :
+    Dot,      // .
+    Ampersand, // &
+    Binary(BinaryOperatorKind),
+    CompoundAssigment(BinaryOperatorKind)
 }
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum BinaryOperatorKind {
@@ -34,6 +44,9 @@ pub enum BinaryOperatorKind {
     Subtraction,    // -
     Multiplication, // *
     Division,       // /
+    Remainder,      // %
+    Power,          // **
+    FloorDivision,  // ~/
     Comparision(ComparisionOpKind)
 }
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -52,7 +65,22 @@ pub enum KeywordKind {
     While,
     Fn,
     Extern,
-    Ret
+    Ret,
+    Test,
+    Import,
+    Pub,
+    For,
+    In,
+    Step,
+    Else,
+    Const,
+    Static,
+    Enum,
+    Match,
+    Trait,
+    Impl,
+    Mut,
+    Defer
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -86,6 +114,21 @@ impl TryFrom<&str> for KeywordKind {
             "fn" => Ok(KeywordKind::Fn),
             "extern" => Ok(KeywordKind::Extern),
             "ret" => Ok(KeywordKind::Ret),
+            "test" => Ok(KeywordKind::Test),
+            "import" => Ok(KeywordKind::Import),
+            "pub" => Ok(KeywordKind::Pub),
+            "for" => Ok(KeywordKind::For),
+            "in" => Ok(KeywordKind::In),
+            "step" => Ok(KeywordKind::Step),
+            "else" => Ok(KeywordKind::Else),
+            "const" => Ok(KeywordKind::Const),
+            "static" => Ok(KeywordKind::Static),
+            "enum" => Ok(KeywordKind::Enum),
+            "match" => Ok(KeywordKind::Match),
+            "trait" => Ok(KeywordKind::Trait),
+            "impl" => Ok(KeywordKind::Impl),
+            "mut" => Ok(KeywordKind::Mut),
+            "defer" => Ok(KeywordKind::Defer),
             _ => Err(())
         }
     }
@@ -102,11 +145,26 @@ impl TryFrom<&str> for OperatorKind {
             "-" => Ok(Self::Binary(BinaryOperatorKind::Subtraction)),
             "*" => Ok(Self::Binary(BinaryOperatorKind::Multiplication)),
             "/" => Ok(Self::Binary(BinaryOperatorKind::Division)),
+            "%" => Ok(Self::Binary(BinaryOperatorKind::Remainder)),
+            "**" => Ok(Self::Binary(BinaryOperatorKind::Power)),
+            "~/" => Ok(Self::Binary(BinaryOperatorKind::FloorDivision)),
             "<" => Ok(Self::Binary(BinaryOperatorKind::Comparision(ComparisionOpKind::Less))),
             ">" => Ok(Self::Binary(BinaryOperatorKind::Comparision(ComparisionOpKind::Bigger))),
             "=" => Ok(Self::Binary(BinaryOperatorKind::Assigment)),
+            "+=" => Ok(Self::CompoundAssigment(BinaryOperatorKind::Addition)),
+            "-=" => Ok(Self::CompoundAssigment(BinaryOperatorKind::Subtraction)),
+            "*=" => Ok(Self::CompoundAssigment(BinaryOperatorKind::Multiplication)),
+            "/=" => Ok(Self::CompoundAssigment(BinaryOperatorKind::Division)),
+            "|" => Ok(Self::Pipe),
+            "=>" => Ok(Self::FatArrow),
+            "->" => Ok(Self::Arrow),
+            ":" => Ok(Self::Colon),
+            "." => Ok(Self::Dot),
+            "&" => Ok(Self::Ampersand),
             ";" => Ok(Self::Semicol),
             "," => Ok(Self::Coma),
+            ".." => Ok(Self::Range),
+            "..=" => Ok(Self::RangeInclusive),
             "(" => Ok(Self::LParen),
             ")" => Ok(Self::RParen),
             "{" => Ok(Self::LCurly),
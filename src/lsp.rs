@@ -0,0 +1,334 @@
+use std::collections::HashMap;
+
+use lsp_server::{Connection, Message, Notification, Request, RequestId, Response};
+use lsp_types::notification::{DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _, PublishDiagnostics};
+use lsp_types::request::{Completion, GotoDefinition, HoverRequest, Request as _};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, Diagnostic, DiagnosticSeverity, DidChangeTextDocumentParams,
+    DidCloseTextDocumentParams, DidOpenTextDocumentParams, GotoDefinitionParams, Hover, HoverContents, HoverParams,
+    HoverProviderCapability, Location, MarkupContent, MarkupKind, OneOf, Position, PublishDiagnosticsParams, Range, ServerCapabilities,
+    TextDocumentPositionParams, TextDocumentSyncCapability, TextDocumentSyncKind, Url,
+};
+use serde_json::Value;
+
+use crate::ast::{parser, Ast, Proto};
+use crate::input::CursoredFile;
+use crate::{abort, pass, recover};
+
+struct Document {
+    text: Vec<char>,
+    /// The last version of the document that has been parsed successfully
+    asts: Vec<Ast>,
+}
+
+/// Speaks the Language Server Protocol over stdio until the client asks to exit
+pub fn serve() {
+    colored::control::set_override(false);
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        definition_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        completion_provider: Some(CompletionOptions::default()),
+        ..Default::default()
+    };
+    if let Err(e) = connection.initialize(serde_json::to_value(capabilities).unwrap()) {
+        abort!("LSP initialization failed: {}", e);
+    }
+
+    let mut documents = HashMap::<Url, Document>::new();
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request).unwrap_or(true) {
+                    break;
+                }
+                let response = handle_request(&documents, request);
+                connection.sender.send(Message::Response(response)).unwrap();
+            }
+            Message::Notification(notification) => {
+                if let Some(diagnostics) = handle_notification(&mut documents, notification) {
+                    let notification = Notification::new(PublishDiagnostics::METHOD.into(), diagnostics);
+                    connection.sender.send(Message::Notification(notification)).unwrap();
+                }
+            }
+            Message::Response(_) => (),
+        }
+    }
+    drop(connection);
+    io_threads.join().unwrap();
+}
+fn handle_notification(documents: &mut HashMap<Url, Document>, notification: Notification) -> Option<PublishDiagnosticsParams> {
+    let (uri, text) = match notification.method.as_str() {
+        DidOpenTextDocument::METHOD => {
+            let params: DidOpenTextDocumentParams = serde_json::from_value(notification.params).ok()?;
+            (params.text_document.uri, params.text_document.text)
+        }
+        DidChangeTextDocument::METHOD => {
+            let mut params: DidChangeTextDocumentParams = serde_json::from_value(notification.params).ok()?;
+            (params.text_document.uri, params.content_changes.pop()?.text)
+        }
+        DidCloseTextDocument::METHOD => {
+            let params: DidCloseTextDocumentParams = serde_json::from_value(notification.params).ok()?;
+            documents.remove(&params.text_document.uri);
+            return Some(PublishDiagnosticsParams::new(params.text_document.uri, vec![], None));
+        }
+        _ => return None,
+    };
+    let text: Vec<char> = text.chars().collect();
+    let mut input = CursoredFile::from_source(uri.path(), text.iter().collect::<String>());
+    let mut diagnostics = vec![];
+    match recover(|| parser::parse(&mut input)) {
+        Ok(asts) => {
+            if let Err(diagnostic) = recover(|| pass::pipeline(&asts, &mut input, &mut pass::Enums::new())) {
+                diagnostics.push(to_lsp_diagnostic(&text, diagnostic));
+            }
+            documents.insert(uri.clone(), Document { text, asts });
+        }
+        Err(diagnostic) => {
+            diagnostics.push(to_lsp_diagnostic(&text, diagnostic));
+            let asts = documents.remove(&uri).map(|d| d.asts).unwrap_or_default();
+            documents.insert(uri.clone(), Document { text, asts });
+        }
+    }
+    Some(PublishDiagnosticsParams::new(uri, diagnostics, None))
+}
+fn handle_request(documents: &HashMap<Url, Document>, request: Request) -> Response {
+    let id = request.id.clone();
+    let result = match request.method.as_str() {
+        GotoDefinition::METHOD => {
+            serde_json::from_value::<GotoDefinitionParams>(request.params).map(|p| definition(documents, p.text_document_position_params))
+        }
+        HoverRequest::METHOD => serde_json::from_value::<HoverParams>(request.params).map(|p| hover(documents, p.text_document_position_params)),
+        Completion::METHOD => serde_json::from_value::<CompletionParams>(request.params).map(|p| completion(documents, p.text_document_position)),
+        _ => return Response::new_err(id, lsp_server::ErrorCode::MethodNotFound as i32, format!("unknown request {}", request.method)),
+    };
+    match result {
+        Ok(result) => Response::new_ok(id, result),
+        Err(e) => invalid_params(id, &e),
+    }
+}
+fn invalid_params(id: RequestId, error: &serde_json::Error) -> Response {
+    Response::new_err(id, lsp_server::ErrorCode::InvalidParams as i32, error.to_string())
+}
+fn definition(documents: &HashMap<Url, Document>, params: TextDocumentPositionParams) -> Value {
+    let Some((document, offset)) = lookup(documents, &params) else {
+        return Value::Null;
+    };
+    let word = word_at(&document.text, offset);
+    let start = if let Some(proto) = functions(&document.asts).find(|p| p.name == word) {
+        proto.start
+    } else if let Some(start) = global_position(&document.text, &document.asts, &word) {
+        start
+    } else if let Some(start) = enclosing_function(&document.asts, offset).and_then(|body| let_position(&document.text, body, &word)) {
+        start
+    } else {
+        return Value::Null;
+    };
+    let location = Location::new(params.text_document.uri, range(&document.text, start, word.chars().count()));
+    serde_json::to_value(location).unwrap()
+}
+fn hover(documents: &HashMap<Url, Document>, params: TextDocumentPositionParams) -> Value {
+    let Some((document, offset)) = lookup(documents, &params) else {
+        return Value::Null;
+    };
+    let word = word_at(&document.text, offset);
+    let Some(proto) = functions(&document.asts).find(|p| p.name == word) else {
+        return Value::Null;
+    };
+    let hover = Hover {
+        contents: HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: format!("```pitusya\n{}\n```\nTakes {} argument(s)", signature(&document.asts, proto), proto.args.len()),
+        }),
+        range: None,
+    };
+    serde_json::to_value(hover).unwrap()
+}
+fn completion(documents: &HashMap<Url, Document>, params: TextDocumentPositionParams) -> Value {
+    let Some((document, offset)) = lookup(documents, &params) else {
+        return Value::Null;
+    };
+    let mut items: Vec<CompletionItem> = functions(&document.asts)
+        .map(|proto| CompletionItem {
+            label: proto.name.clone(),
+            kind: Some(CompletionItemKind::FUNCTION),
+            detail: Some(signature(&document.asts, proto)),
+            ..Default::default()
+        })
+        .collect();
+    items.extend(document.asts.iter().filter_map(|ast| match ast {
+        Ast::GlobalNode { name, mutable, .. } => Some(CompletionItem {
+            label: name.clone(),
+            kind: Some(if *mutable { CompletionItemKind::VARIABLE } else { CompletionItemKind::CONSTANT }),
+            ..Default::default()
+        }),
+        _ => None,
+    }));
+    if let Some(Ast::FunctionNode { proto, body, .. } | Ast::TestNode { proto, body }) = enclosing_function(&document.asts, offset) {
+        let mut variables: Vec<&str> = proto.args.iter().filter_map(identifier).collect();
+        collect_lets(body, &mut variables);
+        variables.sort_unstable();
+        variables.dedup();
+        items.extend(variables.into_iter().map(|name| CompletionItem {
+            label: name.into(),
+            kind: Some(CompletionItemKind::VARIABLE),
+            ..Default::default()
+        }));
+    }
+    serde_json::to_value(items).unwrap()
+}
+fn lookup<'a>(documents: &'a HashMap<Url, Document>, params: &TextDocumentPositionParams) -> Option<(&'a Document, usize)> {
+    let document = documents.get(&params.text_document.uri)?;
+    let offset = offset(&document.text, params.position);
+    Some((document, offset))
+}
+fn functions(asts: &[Ast]) -> impl Iterator<Item = &Proto> {
+    asts.iter().filter_map(|ast| match ast {
+        Ast::FunctionNode { proto, .. } | Ast::ExternNode(proto) => Some(proto),
+        _ => None,
+    })
+}
+fn signature(asts: &[Ast], proto: &Proto) -> String {
+    let keyword = match asts.iter().find(|ast| matches!(ast, Ast::ExternNode(p) | Ast::FunctionNode { proto: p, .. } if p == proto)) {
+        Some(Ast::ExternNode(_)) => "extern",
+        Some(Ast::FunctionNode { public: true, .. }) => "pub fn",
+        _ => "fn",
+    };
+    let args: Vec<String> = proto
+        .args
+        .iter()
+        .filter_map(identifier)
+        .zip(proto.types.iter().chain(std::iter::repeat(&None)))
+        .map(|(arg, ty)| ty.as_ref().map_or_else(|| arg.into(), |ty| format!("{arg}: {ty}")))
+        .collect();
+    let generics = if proto.generics.is_empty() { String::new() } else { format!("<{}>", proto.generics.join(", ")) };
+    let returns = proto.returns.as_ref().map(|ty| format!(" -> {ty}")).unwrap_or_default();
+    format!("{keyword} {}{generics}({}){returns}", proto.name, args.join(", "))
+}
+fn identifier(ast: &Ast) -> Option<&str> {
+    match ast {
+        Ast::IdentifierNode(ident) => Some(ident),
+        _ => None,
+    }
+}
+fn enclosing_function(asts: &[Ast], offset: usize) -> Option<&Ast> {
+    asts.iter()
+        .filter(|ast| matches!(ast, Ast::FunctionNode { .. } | Ast::TestNode { .. }))
+        .take_while(|ast| ast.start().is_some_and(|start| start <= offset))
+        .last()
+}
+fn collect_lets<'a>(body: &'a [Ast], variables: &mut Vec<&'a str>) {
+    for ast in body {
+        match ast {
+            Ast::LetNode { assignee, .. } => variables.push(assignee),
+            Ast::DestructureNode { names, .. } => variables.extend(names.iter().filter(|name| *name != "_").map(String::as_str)),
+            Ast::ForNode { variable, body, .. } | Ast::ForEachNode { variable, body, .. } => {
+                variables.push(variable);
+                collect_lets(body, variables);
+            }
+            _ => ast.blocks().into_iter().for_each(|body| collect_lets(body, variables)),
+        }
+    }
+}
+/// Finds where the name of the global is written in its `const` or `static`
+fn global_position(text: &[char], asts: &[Ast], name: &str) -> Option<usize> {
+    asts.iter().find_map(|ast| match ast {
+        Ast::GlobalNode { name: global, start, mutable, .. } if global == name => {
+            let after_keyword = start + if *mutable { "static".len() } else { "const".len() };
+            text[after_keyword..].iter().position(|c| !c.is_whitespace()).map(|skipped| after_keyword + skipped)
+        }
+        _ => None,
+    })
+}
+/// Finds where the name of the variable is written in its `let`
+fn let_position(text: &[char], function: &Ast, name: &str) -> Option<usize> {
+    let (Ast::FunctionNode { body, .. } | Ast::TestNode { body, .. }) = function else {
+        return None;
+    };
+    let mut stack = vec![body.as_slice()];
+    while let Some(body) = stack.pop() {
+        for ast in body {
+            match ast {
+                Ast::LetNode { assignee, start, .. } if assignee == name => {
+                    let after_let = start + "let".len();
+                    return text[after_let..].iter().position(|c| !c.is_whitespace()).map(|skipped| after_let + skipped);
+                }
+                Ast::ForNode { variable, start, .. } | Ast::ForEachNode { variable, start, .. } if variable == name => {
+                    let after_for = start + "for".len();
+                    return text[after_for..].iter().position(|c| !c.is_whitespace()).map(|skipped| after_for + skipped);
+                }
+                Ast::DestructureNode { names, start, .. } if names.iter().any(|n| n == name) => {
+                    // The names are the only words between the parentheses
+                    let mut offset = start + "let".len();
+                    while offset < text.len() && text[offset] != '=' {
+                        if is_identifier_char(text[offset]) {
+                            if word_at(text, offset) == name {
+                                return Some(offset);
+                            }
+                            offset += text[offset..].iter().take_while(|c| is_identifier_char(**c)).count();
+                        } else {
+                            offset += 1;
+                        }
+                    }
+                    return None;
+                }
+                _ => stack.extend(ast.blocks()),
+            }
+        }
+    }
+    None
+}
+fn is_identifier_char(c: char) -> bool {
+    c == '_' || c.is_ascii_alphanumeric()
+}
+fn word_at(text: &[char], offset: usize) -> String {
+    let offset = offset.min(text.len());
+    let start = text[..offset].iter().rposition(|c| !is_identifier_char(*c)).map_or(0, |i| i + 1);
+    let end = text[offset..].iter().position(|c| !is_identifier_char(*c)).map_or(text.len(), |i| offset + i);
+    text[start..end].iter().collect()
+}
+fn to_lsp_diagnostic(text: &[char], diagnostic: crate::Diagnostic) -> Diagnostic {
+    let (start, len) = diagnostic.span.unwrap_or((0, 0));
+    Diagnostic {
+        range: range(text, start, len),
+        severity: Some(DiagnosticSeverity::ERROR),
+        source: Some("pitusya".into()),
+        message: diagnostic.message,
+        ..Default::default()
+    }
+}
+fn range(text: &[char], start: usize, len: usize) -> Range {
+    Range::new(position(text, start), position(text, start + len))
+}
+fn position(text: &[char], offset: usize) -> Position {
+    let mut position = Position::new(0, 0);
+    for c in &text[..offset.min(text.len())] {
+        if *c == '\n' {
+            position.line += 1;
+            position.character = 0;
+        } else {
+            position.character += c.len_utf16() as u32;
+        }
+    }
+    position
+}
+fn offset(text: &[char], position: Position) -> usize {
+    let mut line = 0;
+    let mut character = 0;
+    for (i, c) in text.iter().enumerate() {
+        if line == position.line && character >= position.character {
+            return i;
+        }
+        if *c == '\n' {
+            if line == position.line {
+                return i;
+            }
+            line += 1;
+            character = 0;
+        } else {
+            character += c.len_utf16() as u32;
+        }
+    }
+    text.len()
+}
@@ -1,13 +1,16 @@
+use std::fs;
+use std::path::PathBuf;
 use std::process::ExitCode;
 
 use clap::Parser;
+use colored::Colorize;
 
-use pitusya::ast::parser;
-use pitusya::ast::Ast;
-use pitusya::codegen::Cg;
-use pitusya::input::{CursoredFile, Cli};
-use pitusya::pass;
-use pitusya::abort;
+use pitusya::codegen::{Cg, Outcome};
+use pitusya::construct_error_message;
+use pitusya::input::{Cli, Command, CursoredFile};
+use pitusya::lexer::token_at;
+use pitusya::{fmt, import, lsp};
+use pitusya::{abort, abort_if_not};
 
 fn main() -> ExitCode {
     let cli = match Cli::try_parse() {
@@ -17,14 +20,107 @@ fn main() -> ExitCode {
             abort!() // I need my special exit code ^.^
         }
     };
-    let mut input = CursoredFile::new(cli);
+    match cli.command {
+        Some(Command::Run { file, args }) => run(Cli {
+            command: None,
+            file: Some(file),
+            args,
+            ..cli
+        }),
+        Some(Command::Fmt { files, check }) => format(files, check),
+        Some(Command::Test { file }) => test(file, cli.no_prelude),
+        Some(Command::Lsp) => {
+            lsp::serve();
+            ExitCode::SUCCESS
+        }
+        None => run(cli),
+    }
+}
+fn run(cli: Cli) -> ExitCode {
+    let input = CursoredFile::new(cli.file.unwrap());
 
-    let mut cg = Cg::default();
+    abort_if_not!(
+        cli.target.target.is_none() || cli.output.is_some(),
+        "Code built for another target cannot be run. Consider passing `--output`"
+    );
+    abort_if_not!(
+        cli.output.is_none() || cli.args.is_empty(),
+        "Arguments are passed to compiled executables when they are run"
+    );
+    let args: Vec<f64> = cli
+        .args
+        .iter()
+        .map(|arg| match arg.parse() {
+            Ok(n) => n,
+            Err(_) => abort!("Argument {} is not a number", arg),
+        })
+        .collect();
+    let mut cg = Cg::new(&cli.target);
+    if cli.debug {
+        cg.enable_debug_info(&input);
+    }
 
-    let asts: Vec<Ast> = parser::parse(&mut input);
-    pass::pipeline(&asts);
-    asts.into_iter().for_each(|ast| cg.codegen(ast));
+    cg.generate(import::load(input, !cli.no_prelude));
 
+    if let Some(output) = cli.output {
+        cg.emit(&output);
+        return ExitCode::SUCCESS;
+    }
     #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
-    ExitCode::from(cg.exec() as u8)
+    ExitCode::from(cg.exec(&args) as u8)
+}
+fn test(file: PathBuf, no_prelude: bool) -> ExitCode {
+    let mut cg = Cg::default();
+    let modules = import::load(CursoredFile::new(file), !no_prelude);
+    // Only the tests of the file itself are run, and it is loaded last
+    let mut input = modules.last().unwrap().file.clone();
+    cg.generate(modules);
+
+    let results = cg.test();
+    println!("running {} tests", results.len());
+    let mut failed = 0;
+    for (name, outcome) in &results {
+        if *outcome == Outcome::Passed {
+            println!("test {name} ... {}", "ok".bright_green());
+            continue;
+        }
+        failed += 1;
+        println!("test {name} ... {}", "FAILED".bright_red());
+        match outcome {
+            Outcome::Failed(position) => {
+                let assert = token_at(&mut input, *position);
+                println!("{}", construct_error_message(&assert, &input, "assertion failed"));
+            }
+            _ => println!("{}: the test was stopped before it finished", "note".bright_cyan()),
+        }
+    }
+    let status = if failed == 0 { "ok".bright_green() } else { "FAILED".bright_red() };
+    println!("\ntest result: {status}. {} passed; {failed} failed", results.len() - failed);
+    if failed == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+fn format(files: Vec<PathBuf>, check: bool) -> ExitCode {
+    let mut unformatted = false;
+    for file in files {
+        let mut input = CursoredFile::new(&file);
+        let formatted = fmt::format(&mut input);
+        let original: &str = input.as_ref();
+        if formatted == original {
+            continue;
+        }
+        if check {
+            eprintln!("{}: {} is not formatted", "error".bright_red(), file.display().to_string().bright_cyan());
+            unformatted = true;
+        } else if fs::write(&file, formatted).is_err() {
+            abort!("Could not write to {}", file.display());
+        }
+    }
+    if unformatted {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
 }
@@ -1,13 +1,49 @@
+use std::collections::HashMap;
+
 use colored::Colorize;
 
-use crate::abort_if_not;
-use crate::ast::Ast;
+use crate::ast::{Arm, Ast, Pattern};
+use crate::input::CursoredFile;
+use crate::lexer::token_at;
+use crate::{abort, abort_if_not, construct_warning_message};
+
+/// Variants of every enum and how many fields they have
+pub type Enums = HashMap<String, Vec<(String, usize)>>;
 
-pub fn pipeline(asts: &[Ast]) {
+/// Checks the file, knowing the enums of the files it imports from `enums` and adding its own ones there
+pub fn pipeline(asts: &[Ast], input: &mut CursoredFile, enums: &mut Enums) {
     for ast in asts {
-        if let Ast::FunctionNode { proto, body } = ast {
-            terminated(&proto.name, body);
-            no_dead_code(&proto.name, body);
+        if let Ast::EnumNode { name, variants, .. } = ast {
+            enums.insert(name.clone(), variants.iter().map(|v| (v.name.clone(), v.fields.len())).collect());
+        }
+    }
+    let methods = asts.iter().flat_map(|ast| match ast {
+        Ast::ImplNode { functions, .. } => functions.as_slice(),
+        _ => &[]
+    });
+    for ast in asts.iter().chain(methods) {
+        match ast {
+            Ast::FunctionNode { proto, body, .. } => {
+                terminated(&proto.name, body);
+                no_dead_code(&proto.name, body);
+                exhaustive(&proto.name, body, input, enums);
+            }
+            Ast::TestNode { proto, body } => {
+                no_return(&proto.name, body);
+                exhaustive(&proto.name, body, input, enums);
+            }
+            _ => ()
+        }
+    }
+}
+fn no_return(name: &str, body: &[Ast]) {
+    let mut stack = vec![body];
+    while let Some(body) = stack.pop() {
+        for ast in body {
+            match ast {
+                Ast::RetNode { .. } => abort!("{}: test {} cannot return values", "error".bright_red(), name.bright_cyan()),
+                _ => stack.extend(ast.blocks())
+            }
         }
     }
 }
@@ -17,30 +53,150 @@ fn terminated(name: &str, body: &[Ast]) {
     let mut counter = 0;
     for ast in body {
         match ast {
-            Ast::WhileNode { body, .. } | Ast::IfNode { body, .. } => stack.push(body),
-            Ast::RetNode(_) => counter += 1,
-            _ => (),
+            Ast::RetNode { .. } => counter += 1,
+            _ => stack.extend(ast.blocks())
         }
     }
-    abort_if_not!(counter == 1, "{}: function {} returns multiple values or returns nothing", "error".bright_red(), name.bright_cyan());
+    abort_if_not!(
+        counter == 1,
+        "{}: function {} returns multiple values or returns nothing",
+        "error".bright_red(),
+        name.bright_cyan()
+    );
     stack.into_iter().for_each(|block| {
-        let count = block.iter().filter(|ast| matches!(ast, Ast::RetNode(_))).count();
-        abort_if_not!(count <= 1, "{}: function {} returns multiple values", "error".bright_red(), name.bright_cyan());
+        let count = block.iter().filter(|ast| matches!(ast, Ast::RetNode { .. })).count();
+        abort_if_not!(
+            count <= 1,
+            "{}: function {} returns multiple values",
+            "error".bright_red(),
+            name.bright_cyan()
+        );
     });
 }
 fn no_dead_code(name: &str, body: &[Ast]) {
-    let mut stack = vec![body]; 
+    let mut stack = vec![body];
     while let Some(body) = stack.pop() {
-        body.iter()
-            .filter(|ast| matches!(ast, Ast::IfNode { .. } | Ast::WhileNode { .. }))
-            .for_each(|body| match body {
-                Ast::IfNode { body, .. } | Ast::WhileNode { body, .. } => stack.push(body),
-                _ => (),
-            });
+        body.iter().for_each(|ast| stack.extend(ast.blocks()));
         abort_if_not!(
-            body.iter().skip_while(|ast| !matches!(ast, Ast::RetNode(_))).nth(1).is_none(),
+            body.iter().skip_while(|ast| !matches!(ast, Ast::RetNode { .. })).nth(1).is_none(),
             "Error: unreachable code in function {}",
             name
         );
     }
-}
\ No newline at end of file
+}
+/// Checks that every `match` covers each variant of its enum, or has a `_`. Numbers are only covered by a `_`
+fn exhaustive(name: &str, body: &[Ast], input: &mut CursoredFile, enums: &Enums) {
+    let mut stack: Vec<&Ast> = body.iter().collect();
+    while let Some(ast) = stack.pop() {
+        if let Ast::MatchNode { arms, .. } = ast {
+            check_arms(name, arms, input, enums);
+        }
+        stack.extend(ast.expressions());
+        stack.extend(ast.blocks().into_iter().flatten());
+    }
+}
+fn check_arms(function: &str, arms: &[Arm], input: &mut CursoredFile, enums: &Enums) {
+    let error = "error".bright_red();
+    let function = function.bright_cyan();
+    let mut matched: Option<&str> = None;
+    let mut numbers = false;
+    let mut wildcard = false;
+    let mut variants = Vec::new();
+    // Ranges the numbers are in, with whether they include their ends
+    let mut ranges: Vec<(f64, f64, bool)> = Vec::new();
+    for arm in arms {
+        let reachable = match &arm.pattern {
+            Pattern::Wildcard => true,
+            Pattern::Variant { name, bindings } => {
+                let Some((enumeration, variant)) = name.rsplit_once("::") else {
+                    abort!("{}: {} is not a variant of an enum in function {}", error, name, function)
+                };
+                if let Some(matched) = matched.filter(|matched| *matched != enumeration) {
+                    abort!(
+                        "{}: match in function {} mixes variants of {} and {}",
+                        error,
+                        function,
+                        matched,
+                        enumeration
+                    );
+                }
+                matched = Some(enumeration);
+                // Enums of files that are not loaded are left to the code generation
+                if let Some(fields) = enums.get(enumeration).map(|variants| variants.iter().find(|(v, _)| v == variant)) {
+                    let Some((_, fields)) = fields else {
+                        abort!(
+                            "{}: enum {} has no variant {} in function {}",
+                            error,
+                            enumeration,
+                            variant,
+                            function
+                        )
+                    };
+                    abort_if_not!(
+                        bindings.len() == *fields,
+                        "{}: {} has {} fields, but the pattern in function {} binds {}",
+                        error,
+                        name,
+                        fields,
+                        function,
+                        bindings.len()
+                    );
+                }
+                let reachable = !variants.contains(&variant);
+                variants.push(variant);
+                reachable
+            }
+            Pattern::Number(n) => {
+                numbers = true;
+                let reachable = !ranges.iter().any(|range| contains(*range, (*n, *n, true)));
+                ranges.push((*n, *n, true));
+                reachable
+            }
+            Pattern::Range { from, to, inclusive } => {
+                numbers = true;
+                let range = (*from, *to, *inclusive);
+                let empty = from > to || (from == to && !inclusive);
+                let reachable = !empty && !ranges.iter().any(|covered| contains(*covered, range));
+                ranges.push(range);
+                reachable
+            }
+        };
+        if wildcard || !reachable {
+            let token = token_at(input, arm.start);
+            eprintln!(
+                "{}",
+                construct_warning_message(&token, input, format!("unreachable pattern in function {function}"))
+            );
+        }
+        wildcard |= matches!(arm.pattern, Pattern::Wildcard);
+    }
+    abort_if_not!(
+        !numbers || matched.is_none(),
+        "{}: match in function {} mixes variants and numbers",
+        error,
+        function
+    );
+    if wildcard {
+        return;
+    }
+    abort_if_not!(!numbers, "{}: match over numbers in function {} needs a `_` arm", error, function);
+    let Some(all) = matched.and_then(|matched| enums.get(matched)) else {
+        return;
+    };
+    let missing: Vec<String> = all
+        .iter()
+        .filter(|(variant, _)| !variants.contains(&variant.as_str()))
+        .map(|(variant, _)| format!("{}::{variant}", matched.unwrap()))
+        .collect();
+    abort_if_not!(
+        missing.is_empty(),
+        "{}: match in function {} does not cover {}",
+        error,
+        function,
+        missing.join(", ")
+    );
+}
+/// Whether every number of the second range is in the first one
+fn contains((from, to, inclusive): (f64, f64, bool), (inner_from, inner_to, inner_inclusive): (f64, f64, bool)) -> bool {
+    from <= inner_from && (inner_to < to || (inner_to == to && (inclusive || !inner_inclusive)))
+}